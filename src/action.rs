@@ -1,27 +1,210 @@
+use serde::{Deserialize, Serialize};
+
 use crate::{
-    project::{Note, Track},
+    project::{
+        BendPoint, ControlLane, ControlPoint, Note, NoteId, TempoEvent, TimeSignatureChange, Track,
+        TrackId,
+    },
     Model,
 };
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum Action {
     RenameProject(String),
     SetBpm(f64),
+    CreateTempoEvent(TempoEvent),
+    DeleteTempoEvent(usize),
+    EditTempoEvent(usize, TempoEvent),
     SetTimeSignatureTop(u32),
     SetTimeSignatureBottom(u32),
-    CreateTrack(Track),
-    DeleteTrack(usize),
-    RenameTrack(usize, String),
-    SetTrackInstrument(usize, u8),
-    CreateNote(usize, Note),
-    DeleteNote(usize, usize),
-    EditNote(usize, usize, f64, u8, f64),
+    CreateTimeSignatureChange(TimeSignatureChange),
+    DeleteTimeSignatureChange(usize),
+    EditTimeSignatureChange(usize, TimeSignatureChange),
+    /// Insert a track at the given position.
+    CreateTrack(usize, Track),
+    DeleteTrack(TrackId),
+    RenameTrack(TrackId, String),
+    SetTrackInstrument(TrackId, u8),
+    SetTrackChannel(TrackId, u8),
+    SetTrackBendRange(TrackId, u8),
+    /// Insert a note at the given position in the track's note list.
+    CreateNote(TrackId, usize, Note),
+    DeleteNote(TrackId, NoteId),
+    EditNote(TrackId, NoteId, f64, u8, f64),
+    EditNoteVelocity(TrackId, NoteId, u8),
+    /// Insert a control lane at the given position in the track's lane list.
+    CreateControlLane(TrackId, usize, ControlLane),
+    /// Delete the lane of the given controller.
+    DeleteControlLane(TrackId, u8),
+    /// Replace the points of the given controller's lane.
+    SetControlPoints(TrackId, u8, Vec<ControlPoint>),
+    /// Replace the points of the track's pitch bend curve.
+    SetPitchBend(TrackId, Vec<BendPoint>),
+    /// Several actions performed in order, undone as a single step.
+    Batch(Vec<Action>),
+}
+
+/// Continuous edits closer together than this, in milliseconds, are undone as one step.
+const COALESCE_WINDOW: f64 = 1000.0;
+
+impl Action {
+    /// Whether this action is part of an edit that is made in many small steps.
+    fn is_continuous(&self) -> bool {
+        matches!(
+            self,
+            Action::RenameProject(_)
+                | Action::SetBpm(_)
+                | Action::RenameTrack(..)
+                | Action::SetTrackInstrument(..)
+                | Action::SetTrackBendRange(..)
+        )
+    }
+
+    /// Short description for the history panel.
+    pub fn description(&self) -> String {
+        let description = match self {
+            Action::RenameProject(_) => "Rename project",
+            Action::SetBpm(_) => "Change tempo",
+            Action::CreateTempoEvent(_) => "Add tempo change",
+            Action::DeleteTempoEvent(_) => "Delete tempo change",
+            Action::EditTempoEvent(..) => "Edit tempo change",
+            Action::SetTimeSignatureTop(_) | Action::SetTimeSignatureBottom(_) => {
+                "Change time signature"
+            }
+            Action::CreateTimeSignatureChange(_) => "Add time signature change",
+            Action::DeleteTimeSignatureChange(_) => "Delete time signature change",
+            Action::EditTimeSignatureChange(..) => "Edit time signature change",
+            Action::CreateTrack(..) => "Create track",
+            Action::DeleteTrack(_) => "Delete track",
+            Action::RenameTrack(..) => "Rename track",
+            Action::SetTrackInstrument(..) => "Change instrument",
+            Action::SetTrackChannel(..) => "Change channel",
+            Action::SetTrackBendRange(..) => "Change bend range",
+            Action::CreateNote(..) => "Add note",
+            Action::DeleteNote(..) => "Delete note",
+            Action::EditNote(..) => "Edit note",
+            Action::EditNoteVelocity(..) => "Change velocity",
+            Action::CreateControlLane(..) => "Add automation lane",
+            Action::DeleteControlLane(..) => "Delete automation lane",
+            Action::SetControlPoints(..) => "Edit automation",
+            Action::SetPitchBend(..) => "Edit pitch bend",
+            Action::Batch(actions) => {
+                let descriptions: Vec<String> = actions.iter().map(Action::description).collect();
+                return summarize(&descriptions);
+            }
+        };
+
+        description.to_string()
+    }
+
+    /// Whether this action continues the same edit as `other`, like typing another character
+    /// into a name field. Both actions have the same shape as their inverses, so either may be
+    /// an inverse.
+    fn continues(&self, other: &Action) -> bool {
+        match (self, other) {
+            (Action::RenameProject(_), Action::RenameProject(_)) => true,
+            (Action::SetBpm(_), Action::SetBpm(_)) => true,
+            (Action::RenameTrack(a, _), Action::RenameTrack(b, _)) => a == b,
+            (Action::SetTrackInstrument(a, _), Action::SetTrackInstrument(b, _)) => a == b,
+            (Action::SetTrackBendRange(a, _), Action::SetTrackBendRange(b, _)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// Edits being grouped into a single undo step.
+#[derive(Default)]
+pub struct Transaction {
+    depth: usize,
+    descriptions: Vec<String>,
+    inverses: Vec<Action>,
+}
+
+/// Describe several actions undone as one step.
+fn summarize(descriptions: &[String]) -> String {
+    match descriptions {
+        [] => "No changes".to_string(),
+        [description] => description.clone(),
+        [first, rest @ ..] if rest.iter().all(|description| description == first) => {
+            format!("{} ×{}", first, descriptions.len())
+        }
+        _ => "Multiple edits".to_string(),
+    }
 }
 
 impl Model {
     pub fn perform_action(&mut self, action: Action) {
+        let now = js_sys::Date::now();
+        let description = action.description();
+
+        let coalesce = self.transaction.is_none()
+            && self
+                .last_continuous_edit
+                .map(|time| now - time < COALESCE_WINDOW)
+                .unwrap_or(false)
+            && self
+                .history
+                .last_edit()
+                .map(|last| action.continues(last))
+                .unwrap_or(false);
+
+        self.last_continuous_edit = if action.is_continuous() {
+            Some(now)
+        } else {
+            None
+        };
+
         let inverse = self.perform_action_impl(action);
-        self.undo_stack.push(inverse);
+
+        match &mut self.transaction {
+            Some(transaction) => {
+                transaction.descriptions.push(description);
+                transaction.inverses.push(inverse);
+            }
+            // The last undo step already restores the state from before the edit started.
+            None if coalesce => self.autosave(),
+            None => {
+                self.history.push(description, inverse);
+                self.autosave();
+            }
+        }
+    }
+
+    /// Group the actions performed until `commit_transaction` into a single undo step.
+    /// Nested transactions are merged into the outermost one.
+    pub fn begin_transaction(&mut self) {
+        self.transaction
+            .get_or_insert_with(Transaction::default)
+            .depth += 1;
+    }
+
+    pub fn commit_transaction(&mut self) {
+        let transaction = self
+            .transaction
+            .as_mut()
+            .expect("no transaction in progress");
+        transaction.depth -= 1;
+
+        if transaction.depth > 0 {
+            return;
+        }
+
+        let Transaction {
+            descriptions,
+            mut inverses,
+            ..
+        } = self.transaction.take().unwrap();
+
+        if inverses.is_empty() {
+            return;
+        }
+
+        // The inverses have to be applied in the opposite order to the actions.
+        inverses.reverse();
+
+        self.history
+            .push(summarize(&descriptions), Action::Batch(inverses));
+        self.autosave();
     }
 
     pub fn perform_action_impl(&mut self, action: Action) -> Action {
@@ -32,64 +215,130 @@ impl Model {
                 Action::RenameProject(old_name)
             }
             Action::SetBpm(new_bpm) => {
-                let old_bpm = self.project.bpm;
-                self.project.bpm = new_bpm;
+                let initial_tempo = &mut self.project.tempo_map.events[0];
+                let old_bpm = initial_tempo.bpm;
+                initial_tempo.bpm = new_bpm;
                 Action::SetBpm(old_bpm)
             }
+            Action::CreateTempoEvent(event) => {
+                let events = &mut self.project.tempo_map.events;
+
+                let index = events
+                    .iter()
+                    .position(|other| other.offset > event.offset)
+                    .unwrap_or(events.len());
+
+                events.insert(index, event);
+                Action::DeleteTempoEvent(index)
+            }
+            Action::DeleteTempoEvent(index) => {
+                let event = self.project.tempo_map.events.remove(index);
+                Action::CreateTempoEvent(event)
+            }
+            Action::EditTempoEvent(index, new_event) => {
+                let event = &mut self.project.tempo_map.events[index];
+                let old_event = std::mem::replace(event, new_event);
+                Action::EditTempoEvent(index, old_event)
+            }
             Action::SetTimeSignatureTop(top) => {
-                let old_top = self.project.time_signature.top;
-                self.project.time_signature.top = top;
+                let time_signature = &mut self.project.time_signature_map.changes[0].time_signature;
+                let old_top = time_signature.top;
+                time_signature.top = top;
                 Action::SetTimeSignatureTop(old_top)
             }
             Action::SetTimeSignatureBottom(bottom) => {
-                let old_bottom = self.project.time_signature.bottom;
-                self.project.time_signature.bottom = bottom;
+                let time_signature = &mut self.project.time_signature_map.changes[0].time_signature;
+                let old_bottom = time_signature.bottom;
+                time_signature.bottom = bottom;
                 Action::SetTimeSignatureBottom(old_bottom)
             }
-            Action::CreateTrack(track) => {
-                let old_len = self.project.tracks.len();
-                self.project.tracks.push(track);
+            Action::CreateTimeSignatureChange(change) => {
+                let changes = &mut self.project.time_signature_map.changes;
+
+                let index = changes
+                    .iter()
+                    .position(|other| other.measure > change.measure)
+                    .unwrap_or(changes.len());
+
+                changes.insert(index, change);
+                Action::DeleteTimeSignatureChange(index)
+            }
+            Action::DeleteTimeSignatureChange(index) => {
+                let change = self.project.time_signature_map.changes.remove(index);
+                Action::CreateTimeSignatureChange(change)
+            }
+            Action::EditTimeSignatureChange(index, new_change) => {
+                let change = &mut self.project.time_signature_map.changes[index];
+                let old_change = std::mem::replace(change, new_change);
+                Action::EditTimeSignatureChange(index, old_change)
+            }
+            Action::CreateTrack(index, track) => {
+                let id = track.id;
+                let index = index.min(self.project.tracks.len());
+
+                self.project.tracks.insert(index, track);
 
-                if old_len == 0 {
-                    self.selected_track_index = Some(0);
-                }
+                // Keep the same track selected even though it may have moved.
+                self.selected_track_index = match self.selected_track_index {
+                    None => Some(index),
+                    Some(selected) if selected >= index => Some(selected + 1),
+                    selected => selected,
+                };
 
-                Action::DeleteTrack(old_len)
+                Action::DeleteTrack(id)
             }
-            Action::DeleteTrack(index) => {
-                if let Some(selected_track_index) = self.selected_track_index {
-                    if index == selected_track_index {
-                        self.selected_track_index = None;
-                    }
-                }
+            Action::DeleteTrack(id) => {
+                let index = self.project.track_index(id).expect("no track with this ID");
+
+                self.selected_track_index = match self.selected_track_index {
+                    Some(selected) if selected == index => None,
+                    Some(selected) if selected > index => Some(selected - 1),
+                    selected => selected,
+                };
 
                 let track = self.project.tracks.remove(index);
-                Action::CreateTrack(track)
+                Action::CreateTrack(index, track)
             }
-            Action::RenameTrack(index, new_name) => {
-                let track = &mut self.project.tracks[index];
-                let old_name = track.name.clone();
-                track.name = new_name;
-                Action::RenameTrack(index, old_name)
+            Action::RenameTrack(id, new_name) => {
+                let track = self.project.track_mut(id);
+                let old_name = std::mem::replace(&mut track.name, new_name);
+                Action::RenameTrack(id, old_name)
             }
-            Action::SetTrackInstrument(track_index, instrument) => {
-                let track = &mut self.project.tracks[track_index];
+            Action::SetTrackInstrument(id, instrument) => {
+                let track = self.project.track_mut(id);
                 let old_instrument = track.instrument;
                 track.instrument = instrument;
-                Action::SetTrackInstrument(track_index, old_instrument)
+                Action::SetTrackInstrument(id, old_instrument)
             }
-            Action::CreateNote(track_index, note) => {
-                let track = &mut self.project.tracks[track_index];
-                let note_index = track.notes.len();
-                track.notes.push(note);
-                Action::DeleteNote(track_index, note_index)
+            Action::SetTrackChannel(id, channel) => {
+                let track = self.project.track_mut(id);
+                let old_channel = track.channel;
+                track.channel = channel;
+                Action::SetTrackChannel(id, old_channel)
             }
-            Action::DeleteNote(track_index, note_index) => {
-                let note = self.project.tracks[track_index].notes.remove(note_index);
-                Action::CreateNote(track_index, note)
+            Action::SetTrackBendRange(id, bend_range) => {
+                let track = self.project.track_mut(id);
+                let old_bend_range = track.bend_range;
+                track.bend_range = bend_range;
+                Action::SetTrackBendRange(id, old_bend_range)
+            }
+            Action::CreateNote(track_id, index, note) => {
+                let track = self.project.track_mut(track_id);
+                let note_id = note.id;
+                let index = index.min(track.notes.len());
+
+                track.notes.insert(index, note);
+                Action::DeleteNote(track_id, note_id)
             }
-            Action::EditNote(track_index, note_index, new_offset, new_pitch, new_length) => {
-                let note = &mut self.project.tracks[track_index].notes[note_index];
+            Action::DeleteNote(track_id, note_id) => {
+                let track = self.project.track_mut(track_id);
+                let index = track.note_index(note_id).expect("no note with this ID");
+
+                let note = track.notes.remove(index);
+                Action::CreateNote(track_id, index, note)
+            }
+            Action::EditNote(track_id, note_id, new_offset, new_pitch, new_length) => {
+                let note = self.project.track_mut(track_id).note_mut(note_id);
 
                 let old_offset = note.offset;
                 let old_pitch = note.pitch;
@@ -99,28 +348,85 @@ impl Model {
                 note.pitch = new_pitch;
                 note.length = new_length;
 
-                Action::EditNote(track_index, note_index, old_offset, old_pitch, old_length)
+                Action::EditNote(track_id, note_id, old_offset, old_pitch, old_length)
+            }
+            Action::EditNoteVelocity(track_id, note_id, new_velocity) => {
+                let note = self.project.track_mut(track_id).note_mut(note_id);
+                let old_velocity = note.velocity;
+                note.velocity = new_velocity;
+                Action::EditNoteVelocity(track_id, note_id, old_velocity)
+            }
+            Action::CreateControlLane(track_id, index, lane) => {
+                let lanes = &mut self.project.track_mut(track_id).control_lanes;
+                let controller = lane.controller;
+
+                lanes.insert(index.min(lanes.len()), lane);
+                Action::DeleteControlLane(track_id, controller)
+            }
+            Action::DeleteControlLane(track_id, controller) => {
+                let track = self.project.track_mut(track_id);
+                let index = track
+                    .control_lane_index(controller)
+                    .expect("no lane for this controller");
+
+                let lane = track.control_lanes.remove(index);
+                Action::CreateControlLane(track_id, index, lane)
+            }
+            Action::SetControlPoints(track_id, controller, points) => {
+                let lane = self
+                    .project
+                    .track_mut(track_id)
+                    .control_lane_mut(controller);
+                let old_points = std::mem::replace(&mut lane.points, points);
+                Action::SetControlPoints(track_id, controller, old_points)
+            }
+            Action::SetPitchBend(track_id, points) => {
+                let track = self.project.track_mut(track_id);
+                let old_points = std::mem::replace(&mut track.pitch_bend, points);
+                Action::SetPitchBend(track_id, old_points)
+            }
+            Action::Batch(actions) => {
+                let mut inverses: Vec<Action> = actions
+                    .into_iter()
+                    .map(|action| self.perform_action_impl(action))
+                    .collect();
+
+                inverses.reverse();
+                Action::Batch(inverses)
             }
         }
     }
 
     pub fn undo_last(&mut self) {
-        match self.undo_stack.pop() {
-            None => {}
-            Some(action) => {
-                let inverse = self.perform_action_impl(action);
-                self.redo_stack.push(inverse);
-            }
+        if let Some(index) = self.history.undo_step() {
+            self.step_history(index);
+            self.autosave();
         }
     }
 
     pub fn redo_last(&mut self) {
-        match self.redo_stack.pop() {
-            None => {}
-            Some(action) => {
-                let inverse = self.perform_action_impl(action);
-                self.undo_stack.push(inverse);
-            }
+        if let Some(index) = self.history.redo_step() {
+            self.step_history(index);
+            self.autosave();
         }
     }
+
+    /// Undo and redo edits until the project is in the state after the given history node.
+    pub fn jump_to_history(&mut self, target: usize) {
+        let (undo, redo) = self.history.path_to(target);
+
+        for index in undo.into_iter().chain(redo) {
+            self.step_history(index);
+        }
+
+        self.autosave();
+    }
+
+    fn step_history(&mut self, index: usize) {
+        self.last_continuous_edit = None;
+
+        let action = self.history.start_step(index);
+        let inverse = self.perform_action_impl(action);
+        self.history.finish_step(index, inverse);
+    }
 }
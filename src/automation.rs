@@ -0,0 +1,426 @@
+use yew::events::MouseEvent;
+
+use crate::{
+    action::Action,
+    project::{
+        BendPoint, ControlLane, ControlPoint, Track, TrackId, Zoom, CONTROL_POINT_RADIUS,
+        MIN_INTERVAL,
+    },
+    util::{mouse_x_to_interval, mouse_y_to_bend, mouse_y_to_control_value, relative_mouse_pos},
+    views::{BEND_LANE_HEIGHT, CONTROL_LANE_HEIGHT},
+    Model, MouseOperation,
+};
+
+pub const SUSTAIN_CONTROLLER: u8 = 64;
+
+/// Commonly automated controllers, with short names for lane labels and full names.
+const CONTROLLER_NAMES: [(u8, &str, &str); 12] = [
+    (1, "Mod", "Modulation"),
+    (2, "Breath", "Breath"),
+    (4, "Foot", "Foot pedal"),
+    (5, "Porta", "Portamento time"),
+    (7, "Vol", "Volume"),
+    (10, "Pan", "Pan"),
+    (11, "Expr", "Expression"),
+    (64, "Sus", "Sustain pedal"),
+    (71, "Reso", "Resonance"),
+    (74, "Cutoff", "Brightness"),
+    (91, "Reverb", "Reverb"),
+    (93, "Chorus", "Chorus"),
+];
+
+/// Whether the controller holds a value of its own, rather than being part of other messages
+/// like bank select, data entry and (N)RPN, or being a channel mode message.
+pub fn is_automatable(controller: u8) -> bool {
+    !matches!(controller, 0 | 6 | 32 | 38 | 96..=101 | 120..=127)
+}
+
+pub fn controller_label(controller: u8) -> String {
+    CONTROLLER_NAMES
+        .iter()
+        .find(|(number, _, _)| *number == controller)
+        .map(|(_, label, _)| label.to_string())
+        .unwrap_or_else(|| format!("CC{}", controller))
+}
+
+pub fn controller_name(controller: u8) -> String {
+    match CONTROLLER_NAMES
+        .iter()
+        .find(|(number, _, _)| *number == controller)
+    {
+        Some((_, _, name)) => format!("{} ({})", name, controller),
+        None => format!("Controller {}", controller),
+    }
+}
+
+/// An automation lane of a track.
+#[derive(Clone, Copy, PartialEq)]
+pub enum AutomationLane {
+    /// The lane of a controller.
+    Control(u8),
+    PitchBend,
+}
+
+impl AutomationLane {
+    pub fn height(self) -> f64 {
+        match self {
+            Self::Control(_) => CONTROL_LANE_HEIGHT,
+            Self::PitchBend => BEND_LANE_HEIGHT,
+        }
+    }
+}
+
+/// A point drawn into an automation lane.
+pub trait LanePoint: Copy {
+    /// The point at `offset`, with `value` rounded to the nearest valid one.
+    fn new(offset: f64, value: f64) -> Self;
+    fn offset(&self) -> f64;
+    fn value(&self) -> f64;
+    /// The value at `mouse_y` in a lane `lane_height` pixels high.
+    fn value_at(mouse_y: f64, lane_height: f64) -> f64;
+    fn position(&self, zoom: Zoom, lane_height: f64) -> (f64, f64);
+    /// The points of the lane in the track, unless it has no such lane of these points.
+    fn points_mut(track: &mut Track, lane: AutomationLane) -> Option<&mut Vec<Self>>;
+    fn set_points(track_id: TrackId, lane: AutomationLane, points: Vec<Self>) -> Action;
+}
+
+impl LanePoint for ControlPoint {
+    fn new(offset: f64, value: f64) -> Self {
+        Self {
+            offset,
+            value: value.round() as u8,
+        }
+    }
+
+    fn offset(&self) -> f64 {
+        self.offset
+    }
+
+    fn value(&self) -> f64 {
+        self.value as f64
+    }
+
+    fn value_at(mouse_y: f64, lane_height: f64) -> f64 {
+        mouse_y_to_control_value(mouse_y, lane_height) as f64
+    }
+
+    fn position(&self, zoom: Zoom, lane_height: f64) -> (f64, f64) {
+        (self.screen_x(zoom), self.screen_y(lane_height))
+    }
+
+    fn points_mut(track: &mut Track, lane: AutomationLane) -> Option<&mut Vec<Self>> {
+        match lane {
+            AutomationLane::Control(controller) => {
+                let index = track.control_lane_index(controller)?;
+                Some(&mut track.control_lanes[index].points)
+            }
+            AutomationLane::PitchBend => None,
+        }
+    }
+
+    fn set_points(track_id: TrackId, lane: AutomationLane, points: Vec<Self>) -> Action {
+        match lane {
+            AutomationLane::Control(controller) => {
+                Action::SetControlPoints(track_id, controller, points)
+            }
+            AutomationLane::PitchBend => unreachable!("control points in the pitch bend lane"),
+        }
+    }
+}
+
+impl LanePoint for BendPoint {
+    fn new(offset: f64, value: f64) -> Self {
+        Self {
+            offset,
+            value: value.round() as i16,
+        }
+    }
+
+    fn offset(&self) -> f64 {
+        self.offset
+    }
+
+    fn value(&self) -> f64 {
+        self.value as f64
+    }
+
+    fn value_at(mouse_y: f64, lane_height: f64) -> f64 {
+        mouse_y_to_bend(mouse_y, lane_height) as f64
+    }
+
+    fn position(&self, zoom: Zoom, lane_height: f64) -> (f64, f64) {
+        (self.screen_x(zoom), self.screen_y(lane_height))
+    }
+
+    fn points_mut(track: &mut Track, lane: AutomationLane) -> Option<&mut Vec<Self>> {
+        match lane {
+            AutomationLane::PitchBend => Some(&mut track.pitch_bend),
+            AutomationLane::Control(_) => None,
+        }
+    }
+
+    fn set_points(track_id: TrackId, lane: AutomationLane, points: Vec<Self>) -> Action {
+        match lane {
+            AutomationLane::PitchBend => Action::SetPitchBend(track_id, points),
+            AutomationLane::Control(_) => unreachable!("bend points in a control lane"),
+        }
+    }
+}
+
+/// The index of the topmost point drawn at `(x, y)`.
+fn point_at_position<P: LanePoint>(
+    points: &[P],
+    x: f64,
+    y: f64,
+    lane_height: f64,
+    zoom: Zoom,
+) -> Option<usize> {
+    points.iter().rposition(|point| {
+        let (point_x, point_y) = point.position(zoom, lane_height);
+        let dx = x - point_x;
+        let dy = y - point_y;
+
+        dx * dx + dy * dy <= CONTROL_POINT_RADIUS * CONTROL_POINT_RADIUS * 4.0
+    })
+}
+
+/// Replace the points between `from` and `to` with a straight line, with a point every `step`
+/// whole notes where the value changes.
+pub fn draw_line<P: LanePoint>(points: &mut Vec<P>, from: P, to: P, step: f64) {
+    let (start, end) = if from.offset() <= to.offset() {
+        (from, to)
+    } else {
+        (to, from)
+    };
+
+    points.retain(|point| {
+        point.offset() < start.offset() - 1e-4 || point.offset() > end.offset() + 1e-4
+    });
+
+    let steps = ((end.offset() - start.offset()) / step).round() as usize;
+    let mut line: Vec<P> = Vec::new();
+
+    for index in 0..=steps {
+        let progress = if steps == 0 {
+            1.0
+        } else {
+            index as f64 / steps as f64
+        };
+
+        let point = P::new(
+            start.offset() + (end.offset() - start.offset()) * progress,
+            start.value() + (end.value() - start.value()) * progress,
+        );
+
+        // The end point is kept so the line ends at the value drawn.
+        if index == steps || line.last().map(|last| last.value()) != Some(point.value()) {
+            line.push(point);
+        }
+    }
+
+    let index = points
+        .iter()
+        .position(|point| point.offset() > end.offset())
+        .unwrap_or(points.len());
+
+    points.splice(index..index, line);
+}
+
+impl Model {
+    /// Add a lane for the controller to the selected track, unless it has one already.
+    pub fn add_control_lane(&mut self, controller: u8) {
+        let track = match self.selected_track_index {
+            Some(index) => &self.project.tracks[index],
+            None => return,
+        };
+
+        if track.control_lane_index(controller).is_some() {
+            return;
+        }
+
+        self.perform_action(Action::CreateControlLane(
+            track.id,
+            track.control_lanes.len(),
+            ControlLane {
+                controller,
+                points: Vec::new(),
+            },
+        ));
+    }
+
+    /// Start drawing into a lane of the selected track, or delete the point under the mouse on
+    /// right click. Dragging draws freehand, or a straight line while holding shift.
+    pub fn automation_lane_mouse_down(&mut self, lane: AutomationLane, event: &MouseEvent) -> bool {
+        match lane {
+            AutomationLane::Control(_) => self.lane_mouse_down::<ControlPoint>(lane, event),
+            AutomationLane::PitchBend => self.lane_mouse_down::<BendPoint>(lane, event),
+        }
+    }
+
+    pub fn draw_automation(&mut self, mouse_x: f64, mouse_y: f64) -> bool {
+        match &self.mouse_operation {
+            MouseOperation::DrawControl {
+                lane: AutomationLane::Control(_),
+                ..
+            } => self.draw_lane::<ControlPoint>(mouse_x, mouse_y),
+            MouseOperation::DrawControl {
+                lane: AutomationLane::PitchBend,
+                ..
+            } => self.draw_lane::<BendPoint>(mouse_x, mouse_y),
+            _ => false,
+        }
+    }
+
+    /// Put back the points the lane had before drawing, and set the drawn ones as an action.
+    pub fn finish_drawing_automation(
+        &mut self,
+        track_id: TrackId,
+        lane: AutomationLane,
+        originals: Vec<(f64, f64)>,
+    ) -> bool {
+        match lane {
+            AutomationLane::Control(_) => {
+                self.finish_drawing_lane::<ControlPoint>(track_id, lane, originals)
+            }
+            AutomationLane::PitchBend => {
+                self.finish_drawing_lane::<BendPoint>(track_id, lane, originals)
+            }
+        }
+    }
+
+    fn lane_mouse_down<P: LanePoint>(&mut self, lane: AutomationLane, event: &MouseEvent) -> bool {
+        if !self.mouse_operation.is_none() {
+            return false;
+        }
+
+        let track_index = match self.selected_track_index {
+            Some(index) => index,
+            None => return false,
+        };
+
+        let (mouse_x, mouse_y) = relative_mouse_pos(event);
+        let track = &mut self.project.tracks[track_index];
+        let track_id = track.id;
+
+        let points = match P::points_mut(track, lane) {
+            Some(points) => points,
+            None => return false,
+        };
+
+        match event.buttons() {
+            1 => {
+                let point = P::new(
+                    mouse_x_to_interval(mouse_x, self.project.grid, self.zoom).max(0.0),
+                    P::value_at(mouse_y, lane.height()),
+                );
+
+                let originals = points
+                    .iter()
+                    .map(|point| (point.offset(), point.value()))
+                    .collect();
+                draw_line(points, point, point, MIN_INTERVAL);
+
+                self.mouse_operation = MouseOperation::DrawControl {
+                    track_id,
+                    lane,
+                    line: event.shift_key(),
+                    start: (point.offset(), point.value()),
+                    last: (point.offset(), point.value()),
+                    originals,
+                };
+
+                true
+            }
+            2 => {
+                let index =
+                    match point_at_position(points, mouse_x, mouse_y, lane.height(), self.zoom) {
+                        Some(index) => index,
+                        None => return false,
+                    };
+
+                let mut points = points.clone();
+                points.remove(index);
+
+                self.perform_action(P::set_points(track_id, lane, points));
+
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn draw_lane<P: LanePoint>(&mut self, mouse_x: f64, mouse_y: f64) -> bool {
+        let (track_id, lane, line, start, last, originals) = match &self.mouse_operation {
+            MouseOperation::DrawControl {
+                track_id,
+                lane,
+                line,
+                start,
+                last,
+                originals,
+            } => (*track_id, *lane, *line, *start, *last, originals),
+            _ => return false,
+        };
+
+        let track_index = match self.project.track_index(track_id) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        let point = P::new(
+            mouse_x_to_interval(mouse_x, self.project.grid, self.zoom).max(0.0),
+            P::value_at(mouse_y, lane.height()),
+        );
+        let step = self.project.grid.interval().unwrap_or(MIN_INTERVAL);
+
+        let points = match P::points_mut(&mut self.project.tracks[track_index], lane) {
+            Some(points) => points,
+            None => return false,
+        };
+
+        if line {
+            *points = originals
+                .iter()
+                .map(|&(offset, value)| P::new(offset, value))
+                .collect();
+            draw_line(points, P::new(start.0, start.1), point, step);
+        } else {
+            // Fill the gap left by moving the mouse quickly.
+            draw_line(points, P::new(last.0, last.1), point, step);
+        }
+
+        if let MouseOperation::DrawControl { last, .. } = &mut self.mouse_operation {
+            *last = (point.offset(), point.value());
+        }
+
+        true
+    }
+
+    fn finish_drawing_lane<P: LanePoint>(
+        &mut self,
+        track_id: TrackId,
+        lane: AutomationLane,
+        originals: Vec<(f64, f64)>,
+    ) -> bool {
+        // The track, or the lane, may have been deleted while drawing.
+        let track_index = match self.project.track_index(track_id) {
+            Some(index) => index,
+            None => return true,
+        };
+
+        let points = match P::points_mut(&mut self.project.tracks[track_index], lane) {
+            Some(points) => points,
+            None => return true,
+        };
+
+        let originals = originals
+            .into_iter()
+            .map(|(offset, value)| P::new(offset, value))
+            .collect();
+        let points = std::mem::replace(points, originals);
+
+        self.perform_action(P::set_points(track_id, lane, points));
+
+        true
+    }
+}
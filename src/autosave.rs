@@ -0,0 +1,118 @@
+use gloo_timers::callback::Timeout;
+use web_sys::Storage;
+
+use crate::{
+    history::UndoTree,
+    project::Project,
+    project_file::{load_project, save_project},
+    Model,
+};
+
+const PROJECT_KEY: &str = "web-midi-editor.autosave.project";
+const HISTORY_KEY: &str = "web-midi-editor.autosave.history";
+
+/// How long to wait for edits to pause before saving, in milliseconds.
+const AUTOSAVE_DELAY: u32 = 1000;
+
+/// Most history nodes saved with the session. Older ones are only kept in memory.
+const MAX_SAVED_HISTORY_NODES: usize = 100;
+
+pub struct Session {
+    pub project: Project,
+    /// Stored apart from the project, so a history that no longer deserializes after an update
+    /// doesn't prevent recovering the project itself.
+    pub history: UndoTree,
+}
+
+fn local_storage() -> Option<Storage> {
+    web_sys::window().and_then(|window| window.local_storage().ok().flatten())
+}
+
+/// The session autosaved by the last page load, if any.
+pub fn load_session() -> Option<Session> {
+    let storage = local_storage()?;
+
+    let project = storage.get_item(PROJECT_KEY).ok().flatten()?;
+    let project = load_project(&project).ok()?;
+
+    let history = storage
+        .get_item(HISTORY_KEY)
+        .ok()
+        .flatten()
+        .and_then(|history| serde_json::from_str(&history).ok())
+        .unwrap_or_default();
+
+    Some(Session { project, history })
+}
+
+pub fn clear_session() {
+    if let Some(storage) = local_storage() {
+        storage.remove_item(PROJECT_KEY).ok();
+        storage.remove_item(HISTORY_KEY).ok();
+    }
+}
+
+impl Model {
+    /// Save the session once edits pause, since saving takes longer the longer the history.
+    pub fn autosave(&mut self) {
+        // Editing before answering the restore prompt means the old session is unwanted.
+        self.recovered_session = None;
+
+        let save_session = self.save_session_callback.clone();
+
+        // Replacing the timeout cancels the one set by the previous edit.
+        self.autosave_pending = true;
+        self.autosave_timeout = Some(Timeout::new(AUTOSAVE_DELAY, move || {
+            save_session.emit(());
+        }));
+    }
+
+    /// Save the session right away, if an autosave is pending.
+    pub fn save_session(&mut self) {
+        // The timeout is left alone, since this may run from within its callback.
+        if !std::mem::take(&mut self.autosave_pending) {
+            return;
+        }
+
+        let storage = match local_storage() {
+            Some(storage) => storage,
+            None => return,
+        };
+
+        if storage
+            .set_item(PROJECT_KEY, &save_project(&self.project))
+            .is_err()
+        {
+            return;
+        }
+
+        // Storage quota is limited; losing older history is better than losing the project, so
+        // keep halving the history until it fits.
+        let mut max_nodes = MAX_SAVED_HISTORY_NODES;
+
+        loop {
+            let history =
+                serde_json::to_string(&self.history.pruned(max_nodes)).expect("serialize history");
+
+            if storage.set_item(HISTORY_KEY, &history).is_ok() {
+                break;
+            }
+
+            if max_nodes == 1 {
+                storage.remove_item(HISTORY_KEY).ok();
+                break;
+            }
+
+            max_nodes /= 2;
+        }
+    }
+
+    pub fn restore_session(&mut self) {
+        if let Some(session) = self.recovered_session.take() {
+            self.load_project(session.project);
+            self.history = session.history;
+
+            self.autosave();
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsCast;
+use web_sys::{ClipboardEvent, Event};
+
+use crate::{action::Action, project::Note, util::is_text_field, Model};
+
+/// Identifies clipboard text written by the editor.
+const CLIPBOARD_FORMAT: &str = "web-midi-editor/notes";
+
+/// Notes as written to the system clipboard. Offsets are relative to the earliest note.
+#[derive(Serialize, Deserialize)]
+struct ClipboardContents {
+    format: String,
+    notes: Vec<ClipboardNote>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ClipboardNote {
+    pitch: u8,
+    velocity: u8,
+    offset: f64,
+    length: f64,
+}
+
+fn to_text(notes: &[ClipboardNote]) -> String {
+    let contents = ClipboardContents {
+        format: CLIPBOARD_FORMAT.to_string(),
+        notes: notes.to_vec(),
+    };
+
+    serde_json::to_string(&contents).expect("serialize clipboard")
+}
+
+/// Notes copied by this or another instance of the editor, if the text holds any.
+fn from_text(text: &str) -> Option<Vec<ClipboardNote>> {
+    let contents: ClipboardContents = serde_json::from_str(text).ok()?;
+
+    let valid = contents.format == CLIPBOARD_FORMAT
+        && contents.notes.iter().all(|note| {
+            note.pitch <= 127 && note.velocity <= 127 && note.offset >= 0.0 && note.length > 0.0
+        });
+
+    valid.then_some(contents.notes)
+}
+
+impl Model {
+    /// Handle a copy event, putting the selected notes on the clipboard. Returns whether
+    /// anything was copied.
+    pub fn copy_selected_notes(&mut self, event: &Event) -> bool {
+        if is_text_field(event) {
+            return false;
+        }
+
+        let notes: Vec<&Note> = match self.selected_track_index {
+            None => return false,
+            Some(index) => self.project.tracks[index]
+                .notes
+                .iter()
+                .filter(|note| self.selected_notes.contains(&note.id))
+                .collect(),
+        };
+
+        if notes.is_empty() {
+            return false;
+        }
+
+        let start = notes
+            .iter()
+            .map(|note| note.offset)
+            .fold(f64::INFINITY, f64::min);
+
+        self.clipboard = notes
+            .iter()
+            .map(|note| ClipboardNote {
+                pitch: note.pitch,
+                velocity: note.velocity,
+                offset: note.offset - start,
+                length: note.length,
+            })
+            .collect();
+
+        // Also share the notes with other tabs through the system clipboard.
+        if let Some(data) = event
+            .dyn_ref::<ClipboardEvent>()
+            .and_then(|event| event.clipboard_data())
+        {
+            if data
+                .set_data("text/plain", &to_text(&self.clipboard))
+                .is_ok()
+            {
+                event.prevent_default();
+            }
+        }
+
+        true
+    }
+
+    pub fn cut_selected_notes(&mut self, event: &Event) -> bool {
+        // Notes being dragged are edited again once the mouse is released.
+        if !self.mouse_operation.is_none() {
+            return false;
+        }
+
+        if self.copy_selected_notes(event) {
+            self.delete_selected_notes();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Handle a paste event, inserting the clipboard contents into the selected track at the
+    /// playhead. The pasted notes become the selection.
+    pub fn paste_notes(&mut self, event: &Event) -> bool {
+        if is_text_field(event) {
+            return false;
+        }
+
+        let track_index = match self.selected_track_index {
+            None => return false,
+            Some(index) => index,
+        };
+
+        // Prefer what's on the system clipboard, in case it was copied in another tab.
+        let system_notes = event
+            .dyn_ref::<ClipboardEvent>()
+            .and_then(|event| event.clipboard_data())
+            .and_then(|data| data.get_data("text/plain").ok())
+            .and_then(|text| from_text(&text));
+
+        if let Some(notes) = system_notes {
+            self.clipboard = notes;
+        }
+
+        if self.clipboard.is_empty() {
+            return false;
+        }
+
+        event.prevent_default();
+
+        let track_id = self.project.tracks[track_index].id;
+        let first_index = self.project.tracks[track_index].notes.len();
+        let mut creations = Vec::new();
+
+        self.selected_notes.clear();
+
+        for (index, note) in (first_index..).zip(&self.clipboard) {
+            let id = self.project.new_note_id();
+
+            creations.push(Action::CreateNote(
+                track_id,
+                index,
+                Note {
+                    id,
+                    pitch: note.pitch,
+                    velocity: note.velocity,
+                    offset: self.play_offset + note.offset,
+                    length: note.length,
+                },
+            ));
+
+            self.selected_notes.insert(id);
+        }
+
+        self.begin_transaction();
+
+        for creation in creations {
+            self.perform_action(creation);
+        }
+
+        self.commit_transaction();
+
+        true
+    }
+}
@@ -0,0 +1,318 @@
+use serde::{Deserialize, Serialize};
+
+use crate::action::Action;
+
+/// Most nodes kept in the history. Older ones are dropped to make room for new ones.
+const MAX_NODES: usize = 500;
+
+/// Undo history as a tree of project states. Undoing and then making a new edit starts a new
+/// branch, so the states that were undone stay reachable.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UndoTree {
+    /// Nodes in the order they were created. The first one is the state the history starts in.
+    nodes: Vec<HistoryNode>,
+    current: usize,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HistoryNode {
+    pub parent: Option<usize>,
+    pub description: String,
+    /// Undoes this node if it is applied, that is, if it is the current node or one of its
+    /// ancestors. Otherwise, redoes it from the parent's state.
+    action: Action,
+    /// The child to redo into, the one that was visited last.
+    last_child: Option<usize>,
+}
+
+impl Default for UndoTree {
+    fn default() -> Self {
+        Self {
+            nodes: vec![HistoryNode {
+                parent: None,
+                description: "Initial state".to_string(),
+                action: Action::Batch(Vec::new()),
+                last_child: None,
+            }],
+            current: 0,
+        }
+    }
+}
+
+impl UndoTree {
+    pub fn nodes(&self) -> &[HistoryNode] {
+        &self.nodes
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Record an edit made in the current state, given the action that undoes it.
+    pub fn push(&mut self, description: String, undo_action: Action) {
+        let index = self.nodes.len();
+
+        self.nodes.push(HistoryNode {
+            parent: Some(self.current),
+            description,
+            action: undo_action,
+            last_child: None,
+        });
+
+        self.nodes[self.current].last_child = Some(index);
+        self.current = index;
+
+        self.prune(MAX_NODES);
+    }
+
+    /// A copy of the history keeping at most `max_nodes` nodes, with the current one among them.
+    pub fn pruned(&self, max_nodes: usize) -> Self {
+        let mut pruned = self.clone();
+        pruned.prune(max_nodes.max(1));
+        pruned
+    }
+
+    /// The action undoing the current node, as long as nothing was undone from it. An edit
+    /// continuing that one may be merged into it.
+    pub fn last_edit(&self) -> Option<&Action> {
+        let node = &self.nodes[self.current];
+
+        match (node.parent, node.last_child) {
+            (Some(_), None) => Some(&node.action),
+            _ => None,
+        }
+    }
+
+    /// The node undone by the next undo.
+    pub fn undo_step(&self) -> Option<usize> {
+        self.nodes[self.current].parent.map(|_| self.current)
+    }
+
+    /// The node redone by the next redo.
+    pub fn redo_step(&self) -> Option<usize> {
+        self.nodes[self.current].last_child
+    }
+
+    /// The nodes to undo, then the nodes to redo, to get from the current node to `target`.
+    pub fn path_to(&self, target: usize) -> (Vec<usize>, Vec<usize>) {
+        let current_ancestors = self.ancestors(self.current);
+        let mut redo: Vec<usize> = self
+            .ancestors(target)
+            .into_iter()
+            .take_while(|index| !current_ancestors.contains(index))
+            .collect();
+        redo.reverse();
+
+        let common = match redo.first() {
+            Some(&first) => self.nodes[first].parent,
+            None => Some(target),
+        };
+        let undo = current_ancestors
+            .into_iter()
+            .take_while(|&index| Some(index) != common)
+            .collect();
+
+        (undo, redo)
+    }
+
+    /// For each node, whether it is the current one or one of its ancestors.
+    pub fn applied(&self) -> Vec<bool> {
+        let mut applied = vec![false; self.nodes.len()];
+
+        for index in self.ancestors(self.current) {
+            applied[index] = true;
+        }
+
+        applied
+    }
+
+    /// Take out the action for undoing or redoing the node, to be replaced by its inverse
+    /// with `finish_step`.
+    pub fn start_step(&mut self, index: usize) -> Action {
+        std::mem::replace(&mut self.nodes[index].action, Action::Batch(Vec::new()))
+    }
+
+    pub fn finish_step(&mut self, index: usize, inverse: Action) {
+        let parent = self.nodes[index]
+            .parent
+            .expect("the initial state can't be stepped");
+
+        self.nodes[index].action = inverse;
+        self.nodes[parent].last_child = Some(index);
+
+        self.current = if self.current == index { parent } else { index };
+    }
+
+    /// Drop the oldest nodes beyond `max_nodes`: branches off the current path first, leaf by
+    /// leaf, then the start of the current path.
+    fn prune(&mut self, max_nodes: usize) {
+        while self.nodes.len() > max_nodes {
+            let applied = self.applied();
+            let mut has_child = vec![false; self.nodes.len()];
+
+            for node in &self.nodes {
+                if let Some(parent) = node.parent {
+                    has_child[parent] = true;
+                }
+            }
+
+            let oldest_leaf =
+                (0..self.nodes.len()).find(|&index| !applied[index] && !has_child[index]);
+
+            match oldest_leaf {
+                Some(index) => {
+                    // Parents are always older than their children, so keep their index.
+                    let parent = self.nodes[index]
+                        .parent
+                        .expect("the initial state is always applied");
+
+                    self.remove(index);
+
+                    if self.nodes[parent].last_child.is_none() {
+                        self.nodes[parent].last_child = (0..self.nodes.len())
+                            .rev()
+                            .find(|&child| self.nodes[child].parent == Some(parent));
+                    }
+                }
+                None => {
+                    // Only the current path is left, so its second node becomes the state the
+                    // history starts in.
+                    self.remove(0);
+                    self.nodes[0].action = Action::Batch(Vec::new());
+                }
+            }
+        }
+    }
+
+    /// Remove a node, detaching its children and shifting the indices of the nodes after it.
+    fn remove(&mut self, index: usize) {
+        self.nodes.remove(index);
+
+        let shift = |other: usize| if other > index { other - 1 } else { other };
+
+        for node in &mut self.nodes {
+            node.parent = node.parent.filter(|&parent| parent != index).map(shift);
+            node.last_child = node.last_child.filter(|&child| child != index).map(shift);
+        }
+
+        self.current = shift(self.current);
+    }
+
+    /// The node and its ancestors, starting with the node itself.
+    fn ancestors(&self, index: usize) -> Vec<usize> {
+        let mut ancestors = vec![index];
+
+        while let Some(parent) = self.nodes[*ancestors.last().unwrap()].parent {
+            ancestors.push(parent);
+        }
+
+        ancestors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push(tree: &mut UndoTree, description: &str) {
+        tree.push(description.to_string(), Action::Batch(Vec::new()));
+    }
+
+    fn step(tree: &mut UndoTree, index: usize) {
+        let action = tree.start_step(index);
+        tree.finish_step(index, action);
+    }
+
+    fn descriptions(tree: &UndoTree) -> Vec<&str> {
+        tree.nodes()
+            .iter()
+            .map(|node| node.description.as_str())
+            .collect()
+    }
+
+    /// The initial state with the edit "A", followed by the branches "B", undone, and "C".
+    fn branched_tree() -> UndoTree {
+        let mut tree = UndoTree::default();
+
+        push(&mut tree, "A");
+        push(&mut tree, "B");
+        step(&mut tree, 2);
+        push(&mut tree, "C");
+
+        tree
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let mut tree = branched_tree();
+
+        assert_eq!(tree.current(), 3);
+        assert_eq!(tree.undo_step(), Some(3));
+        assert_eq!(tree.redo_step(), None);
+
+        step(&mut tree, 3);
+        assert_eq!(tree.current(), 1);
+        assert_eq!(tree.redo_step(), Some(3));
+
+        step(&mut tree, 1);
+        assert_eq!(tree.current(), 0);
+        assert_eq!(tree.undo_step(), None);
+    }
+
+    #[test]
+    fn path_between_branches() {
+        let tree = branched_tree();
+
+        assert_eq!(tree.path_to(3), (vec![], vec![]));
+        assert_eq!(tree.path_to(2), (vec![3], vec![2]));
+        assert_eq!(tree.path_to(1), (vec![3], vec![]));
+        assert_eq!(tree.path_to(0), (vec![3, 1], vec![]));
+        assert_eq!(tree.applied(), [true, true, false, true]);
+    }
+
+    #[test]
+    fn prune_drops_undone_branches_first() {
+        let tree = branched_tree().pruned(3);
+
+        assert_eq!(descriptions(&tree), ["Initial state", "A", "C"]);
+        assert_eq!(tree.current(), 2);
+        assert_eq!(tree.nodes()[2].parent, Some(1));
+        assert_eq!(tree.undo_step(), Some(2));
+    }
+
+    #[test]
+    fn prune_drops_start_of_current_path() {
+        let tree = branched_tree().pruned(2);
+
+        assert_eq!(descriptions(&tree), ["A", "C"]);
+        assert_eq!(tree.nodes()[0].parent, None);
+        assert_eq!(tree.current(), 1);
+        assert_eq!(tree.path_to(0), (vec![1], vec![]));
+    }
+
+    #[test]
+    fn prune_keeps_last_child_for_redo() {
+        let mut tree = branched_tree();
+        step(&mut tree, 3);
+
+        // "B" is older, but "C" was visited last.
+        let tree = tree.pruned(3);
+
+        assert_eq!(descriptions(&tree), ["Initial state", "A", "C"]);
+        assert_eq!(tree.current(), 1);
+        assert_eq!(tree.redo_step(), Some(2));
+    }
+
+    #[test]
+    fn push_limits_size() {
+        let mut tree = UndoTree::default();
+
+        for index in 0..MAX_NODES + 10 {
+            push(&mut tree, &index.to_string());
+        }
+
+        assert_eq!(tree.nodes().len(), MAX_NODES);
+        assert_eq!(tree.current(), MAX_NODES - 1);
+        assert_eq!(tree.nodes()[0].parent, None);
+    }
+}
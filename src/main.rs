@@ -1,67 +1,172 @@
-use action::Action;
-use gloo_timers::callback::Interval;
-use js_sys::{Array, Uint8Array};
-use midi::export_midi;
-use views::PIANO_KEYS_WIDTH;
+use action::{Action, Transaction};
+use automation::AutomationLane;
+use autosave::{clear_session, load_session, Session};
+use clipboard::ClipboardNote;
+use gloo_timers::callback::Timeout;
+use history::UndoTree;
+use metronome::MetronomeSettings;
+use midi::{export_midi, import_midi};
+use musical_typing::MusicalTyping;
+use playback::Playback;
+use std::collections::HashSet;
+use views::{
+    LOOP_MARKER_GRAB_WIDTH, PIANO_KEYS_WIDTH, TEMPO_LANE_HEIGHT, VELOCITY_LANE_HEIGHT, ZOOM_STEP,
+};
 use wasm_bindgen::{prelude::Closure, JsCast, JsValue};
-use web_sys::{Blob, HtmlAnchorElement, HtmlElement, MidiAccess, MidiOutput, SvgLineElement, Url};
-use yew::{events::MouseEvent, prelude::*};
+use web_sys::{HtmlElement, MidiAccess, MidiInput, MidiMessageEvent, MidiOutput};
+use yew::{
+    events::{Event, KeyboardEvent, MouseEvent, WheelEvent},
+    prelude::*,
+};
 
 mod action;
+mod automation;
+mod autosave;
+mod clipboard;
+mod history;
+mod metronome;
 mod midi;
+mod musical_typing;
 mod playback;
 mod project;
+mod project_file;
+mod quantize;
+mod recording;
+mod step_input;
 mod util;
 mod views;
 
 use project::{
-    Note, Project, TimeSignature, Track, MIN_INTERVAL, NOTE_EDGE_WIDTH, NOTE_RECT_HEIGHT,
-    WHOLE_NOTE_WIDTH,
+    Grid, Note, NoteId, Project, TempoEvent, TimeSignatureChange, Track, TrackId, Zoom,
+    DEFAULT_BEND_RANGE, MAX_ZOOM, MIN_INTERVAL, MIN_ZOOM, NOTE_EDGE_WIDTH,
+};
+use project_file::{load_project, save_project, PROJECT_FILE_EXTENSION};
+use quantize::QuantizeSettings;
+use recording::Recording;
+use step_input::StepInput;
+use util::{
+    alert, download_file, is_text_field, mouse_x_to_interval, mouse_y_to_bpm, mouse_y_to_pitch,
+    mouse_y_to_velocity, open_file_dialog, relative_mouse_pos,
 };
-use util::{mouse_x_to_interval, mouse_y_to_pitch, relative_mouse_pos, snap};
 
 pub enum Msg {
     MidiAccessGranted(MidiAccess),
     MidiAccessRefused,
     SetOutputDevice(MidiOutput),
+    SetInputDevice(Option<MidiInput>),
+    /// Bytes received from the input device, with their `performance.now()` timestamp.
+    MidiInput(Vec<u8>, f64),
     SelectTrack(usize),
     DeselectTrack,
     CreateTrack,
     DeleteSelectedTrack,
     RenameSelectedTrack(String),
     SetSelectedTrackInstrument(u8),
+    SetSelectedTrackChannel(u8),
+    SetSelectedTrackBendRange(u8),
     SetProjectName(String),
     SetBpm(f64),
     SetTimeSignatureTop(u32),
     SetTimeSignatureBottom(u32),
+    AddTimeSignatureChange,
+    EditTimeSignatureChange(usize, TimeSignatureChange),
+    DeleteTimeSignatureChange(usize),
     ProgressBarMouseDown(MouseEvent),
-    ProgressBarMouseUp,
     PianoRollMouseDown(MouseEvent),
-    PianoRollMouseUp,
+    VelocityLaneMouseDown(MouseEvent),
+    TempoLaneMouseDown(MouseEvent),
+    /// Mouse down on the control lane at the given position in the selected track's lanes.
+    AutomationLaneMouseDown(AutomationLane, MouseEvent),
+    SetNewControlLaneController(u8),
+    AddControlLane(u8),
+    DeleteControlLane(u8),
     MouseMove(MouseEvent),
+    /// Mouse released anywhere in the window.
+    MouseUp,
     TogglePlayback,
+    ToggleRecording,
+    ToggleStepInput,
+    ToggleMusicalTyping,
+    StepInputRest,
+    ToggleLoop,
+    ToggleMetronome,
+    SetMetronomeChannel(u8),
+    SetMetronomeAccentPitch(u8),
+    SetMetronomePitch(u8),
+    SetCountIn(u32),
     SetPlayProgress(f64),
-    IncrementPlayProgress,
-    PlayMidiNote(u8, u8),
+    PlaybackTick,
+    PlayMidiNote(u8, u8, u8),
     ExportMidi,
+    ImportMidi,
+    MidiFileLoaded(String, Vec<u8>),
+    SaveProject,
+    OpenProject,
+    ProjectFileLoaded(String, Vec<u8>),
+    RestoreSession,
+    DiscardSession,
+    /// Save the session now, rather than once the pending autosave is due.
+    SaveSession,
     Undo,
     Redo,
+    JumpToHistory(usize),
+    SetQuantizeGrid(Grid),
+    SetQuantizeStrength(f64),
+    SetQuantizeSwing(f64),
+    SetQuantizeEnds(bool),
+    Quantize,
+    SetGrid(Grid),
+    ZoomHorizontal(f64),
+    ZoomVertical(f64),
+    PianoRollWheel(WheelEvent),
+    KeyDown(KeyboardEvent),
+    KeyUp(KeyboardEvent),
+    MainViewBlur,
+    Copy(Event),
+    Cut(Event),
+    Paste(Event),
+    EndContinuousEdit,
 }
 
 pub struct Model {
     midi_access: Option<MidiAccess>,
     selected_output: Option<MidiOutput>,
+    selected_input: Option<MidiInput>,
     project: Project,
     selected_track_index: Option<usize>,
+    /// Selected notes of the selected track.
+    selected_notes: HashSet<NoteId>,
+    clipboard: Vec<ClipboardNote>,
+    quantize_settings: QuantizeSettings,
+    metronome: MetronomeSettings,
+    /// Controller picked for the next automation lane.
+    new_control_lane_controller: u8,
+    zoom: Zoom,
     mouse_operation: MouseOperation,
     piano_roll_area: NodeRef,
     last_placed_note_length: f64,
-    undo_stack: Vec<Action>,
-    redo_stack: Vec<Action>,
+    history: UndoTree,
+    transaction: Option<Transaction>,
+    /// When the last action that may be merged with the next one was performed.
+    last_continuous_edit: Option<f64>,
     play_offset: f64,
     play_progress: f64,
     progress_line: NodeRef,
-    tick_interval: Option<Interval>,
+    recovered_session: Option<Session>,
+    /// Saves the session once it runs out, unless replaced by a later edit.
+    autosave_timeout: Option<Timeout>,
+    autosave_pending: bool,
+    save_session_callback: Callback<()>,
+    playback: Option<Playback>,
+    recording: Option<Recording>,
+    step_input: Option<StepInput>,
+    musical_typing: Option<MusicalTyping>,
+    /// Handles messages from the selected input device.
+    input_closure: Closure<dyn FnMut(MidiMessageEvent)>,
+    /// Ends mouse operations, even when the mouse is released outside the area they started in.
+    _mouse_up_closure: Closure<dyn FnMut(MouseEvent)>,
+    /// Saves a pending autosave before the page goes away.
+    _page_hide_closure: Closure<dyn FnMut(Event)>,
     _success_closure: Closure<dyn FnMut(JsValue)>,
     _fail_closure: Closure<dyn FnMut(JsValue)>,
 }
@@ -95,27 +200,71 @@ impl Component for Model {
             .expect("request_midi_access")
             .then2(&success, &fail);
 
-        let project = Project {
-            name: "Untitled".to_string(),
-            time_signature: TimeSignature { top: 4, bottom: 4 },
-            bpm: 120.0,
-            tracks: Vec::new(),
-        };
+        let link = ctx.link().clone();
+
+        let input_closure = Closure::wrap(Box::new(move |event: MidiMessageEvent| {
+            if let Ok(data) = event.data() {
+                link.send_message(Msg::MidiInput(data, event.time_stamp()));
+            }
+        }) as Box<dyn FnMut(MidiMessageEvent)>);
+
+        let link = ctx.link().clone();
+
+        let mouse_up_closure = Closure::wrap(Box::new(move |_event: MouseEvent| {
+            link.send_message(Msg::MouseUp);
+        }) as Box<dyn FnMut(MouseEvent)>);
+
+        window
+            .add_event_listener_with_callback("mouseup", mouse_up_closure.as_ref().unchecked_ref())
+            .expect("add_event_listener_with_callback");
+
+        let link = ctx.link().clone();
+
+        let page_hide_closure = Closure::wrap(Box::new(move |_event: Event| {
+            link.send_message(Msg::SaveSession);
+        }) as Box<dyn FnMut(Event)>);
+
+        window
+            .add_event_listener_with_callback(
+                "pagehide",
+                page_hide_closure.as_ref().unchecked_ref(),
+            )
+            .expect("add_event_listener_with_callback");
+
+        let project = Project::new("Untitled");
 
         Self {
             midi_access: None,
             selected_output: None,
+            selected_input: None,
             project,
             selected_track_index: None,
+            selected_notes: HashSet::new(),
+            clipboard: Vec::new(),
+            quantize_settings: QuantizeSettings::default(),
+            metronome: MetronomeSettings::default(),
+            new_control_lane_controller: 1,
+            zoom: Zoom::default(),
             mouse_operation: MouseOperation::None,
             piano_roll_area: NodeRef::default(),
             last_placed_note_length: 1.0 / 8.0,
-            undo_stack: Vec::new(),
-            redo_stack: Vec::new(),
+            history: UndoTree::default(),
+            transaction: None,
+            last_continuous_edit: None,
             play_offset: 0.0,
             play_progress: 0.0,
             progress_line: NodeRef::default(),
-            tick_interval: None,
+            recovered_session: load_session(),
+            autosave_timeout: None,
+            autosave_pending: false,
+            save_session_callback: ctx.link().callback(|_| Msg::SaveSession),
+            playback: None,
+            recording: None,
+            step_input: None,
+            musical_typing: None,
+            input_closure,
+            _mouse_up_closure: mouse_up_closure,
+            _page_hide_closure: page_hide_closure,
             _success_closure: success,
             _fail_closure: fail,
         }
@@ -136,7 +285,13 @@ impl Component for Model {
                     }
                 };
 
-                ctx.link().send_message(Msg::CreateTrack);
+                let input = self.get_input_devices().first().cloned();
+                self.set_input_device(input);
+
+                // Wait for the user to decide whether to restore the last session.
+                if self.recovered_session.is_none() {
+                    ctx.link().send_message(Msg::CreateTrack);
+                }
 
                 true
             }
@@ -148,34 +303,49 @@ impl Component for Model {
             }
             Msg::SetOutputDevice(output) => {
                 self.selected_output = Some(output);
-                self.play_midi_note(0, 60, 1000.0);
+                self.play_midi_note(0, 0, 60, 1000.0);
 
                 true
             }
+            Msg::SetInputDevice(input) => {
+                self.set_input_device(input);
+                true
+            }
+            Msg::MidiInput(data, time) => self.handle_midi_input(&data, time),
             Msg::SelectTrack(index) => {
                 self.selected_track_index = Some(index);
+                self.selected_notes.clear();
                 true
             }
             Msg::DeselectTrack => {
                 self.selected_track_index = None;
+                self.selected_notes.clear();
                 true
             }
             Msg::CreateTrack => {
                 let len = self.project.tracks.len();
 
-                self.perform_action(Action::CreateTrack(Track {
+                let track = Track {
+                    id: self.project.new_track_id(),
                     name: format!("Track {}", len + 1),
                     notes: Vec::new(),
                     instrument: 0,
-                }));
+                    channel: self.project.free_channel(),
+                    control_lanes: Vec::new(),
+                    pitch_bend: Vec::new(),
+                    bend_range: DEFAULT_BEND_RANGE,
+                };
+
+                self.perform_action(Action::CreateTrack(len, track));
 
                 true
             }
             Msg::DeleteSelectedTrack => {
                 if let Some(index) = self.selected_track_index {
-                    self.perform_action(Action::DeleteTrack(index));
+                    let id = self.project.tracks[index].id;
+                    self.perform_action(Action::DeleteTrack(id));
 
-                    if self.project.tracks.len() >= 1 {
+                    if !self.project.tracks.is_empty() {
                         let index = if index == 0 { 0 } else { index - 1 };
                         ctx.link().send_message(Msg::SelectTrack(index));
                     } else {
@@ -188,15 +358,29 @@ impl Component for Model {
                 }
             }
             Msg::RenameSelectedTrack(name) => {
-                if let Some(index) = self.selected_track_index {
-                    self.perform_action(Action::RenameTrack(index, name))
+                if let Some(id) = self.selected_track_id() {
+                    self.perform_action(Action::RenameTrack(id, name))
                 }
 
                 true
             }
             Msg::SetSelectedTrackInstrument(instrument) => {
-                if let Some(index) = self.selected_track_index {
-                    self.perform_action(Action::SetTrackInstrument(index, instrument));
+                if let Some(id) = self.selected_track_id() {
+                    self.perform_action(Action::SetTrackInstrument(id, instrument));
+                }
+
+                true
+            }
+            Msg::SetSelectedTrackChannel(channel) => {
+                if let Some(id) = self.selected_track_id() {
+                    self.perform_action(Action::SetTrackChannel(id, channel));
+                }
+
+                true
+            }
+            Msg::SetSelectedTrackBendRange(bend_range) => {
+                if let Some(id) = self.selected_track_id() {
+                    self.perform_action(Action::SetTrackBendRange(id, bend_range));
                 }
 
                 true
@@ -217,20 +401,93 @@ impl Component for Model {
                 self.perform_action(Action::SetTimeSignatureBottom(bottom));
                 true
             }
+            Msg::AddTimeSignatureChange => {
+                let map = &self.project.time_signature_map;
+
+                // The initial time signature lives at measure zero, so start at the next one.
+                let measure = map.measure_at(self.play_offset).max(1);
+
+                if map.changes.iter().any(|change| change.measure == measure) {
+                    return false;
+                }
+
+                let time_signature = map.time_signature_at(map.measure_offset(measure)).clone();
+
+                self.perform_action(Action::CreateTimeSignatureChange(TimeSignatureChange {
+                    measure,
+                    time_signature,
+                }));
+
+                true
+            }
+            Msg::EditTimeSignatureChange(index, change) => {
+                let changes = &self.project.time_signature_map.changes;
+
+                // Changes can't be moved past their neighbours.
+                let after_previous = changes[index - 1].measure < change.measure;
+                let before_next = changes
+                    .get(index + 1)
+                    .map(|next| change.measure < next.measure)
+                    .unwrap_or(true);
+
+                if after_previous && before_next {
+                    self.perform_action(Action::EditTimeSignatureChange(index, change));
+                }
+
+                true
+            }
+            Msg::DeleteTimeSignatureChange(index) => {
+                self.perform_action(Action::DeleteTimeSignatureChange(index));
+                true
+            }
             Msg::ProgressBarMouseDown(event) => {
                 if let MouseOperation::None = self.mouse_operation {
-                    self.mouse_operation = MouseOperation::DragProgressBar;
-
                     let (mouse_x, _) = relative_mouse_pos(&event);
-                    self.set_play_offset_from_mouse_x(mouse_x);
+
+                    let loop_region = &self.project.loop_region;
+                    let marker_distance = |offset: f64| {
+                        let x = PIANO_KEYS_WIDTH + offset * self.zoom.whole_note_width();
+                        (mouse_x - x).abs()
+                    };
+
+                    // Prefer the end marker, so a collapsed region can still be widened.
+                    if marker_distance(loop_region.end) <= LOOP_MARKER_GRAB_WIDTH {
+                        self.mouse_operation = MouseOperation::DragLoopMarker { end: true };
+                    } else if marker_distance(loop_region.start) <= LOOP_MARKER_GRAB_WIDTH {
+                        self.mouse_operation = MouseOperation::DragLoopMarker { end: false };
+                    } else {
+                        self.mouse_operation = MouseOperation::DragProgressBar;
+                        self.set_play_offset_from_mouse_x(mouse_x);
+                    }
 
                     return true;
                 }
 
                 false
             }
-            Msg::ProgressBarMouseUp => {
-                self.mouse_operation = MouseOperation::None;
+            Msg::ToggleLoop => {
+                self.project.loop_region.enabled = !self.project.loop_region.enabled;
+                self.autosave();
+                true
+            }
+            Msg::ToggleMetronome => {
+                self.metronome.enabled = !self.metronome.enabled;
+                true
+            }
+            Msg::SetMetronomeChannel(channel) => {
+                self.metronome.channel = channel;
+                false
+            }
+            Msg::SetMetronomeAccentPitch(pitch) => {
+                self.metronome.accent_pitch = pitch;
+                true
+            }
+            Msg::SetMetronomePitch(pitch) => {
+                self.metronome.pitch = pitch;
+                true
+            }
+            Msg::SetCountIn(measures) => {
+                self.metronome.count_in = measures;
                 false
             }
             Msg::PianoRollMouseDown(event) => {
@@ -250,50 +507,94 @@ impl Component for Model {
 
                 match event.buttons() {
                     1 => {
-                        let existing_note_index = track.get_note_at_position(mouse_x, mouse_y);
+                        let existing_note_index =
+                            track.get_note_at_position(mouse_x, mouse_y, self.zoom);
 
                         if let Some(note_index) = existing_note_index {
                             let note = &track.notes[note_index];
 
-                            self.mouse_operation = MouseOperation::NoteOperation {
-                                note_index,
-                                type_: {
-                                    if mouse_x <= note.screen_x() + NOTE_EDGE_WIDTH {
-                                        NoteOperationType::DragLeftEdge(note.offset, note.length)
-                                    } else if mouse_x >= note.right_edge() - NOTE_EDGE_WIDTH {
-                                        NoteOperationType::DragRightEdge(note.length)
-                                    } else {
-                                        let grab_offset = mouse_x_to_interval(mouse_x);
+                            if event.shift_key() {
+                                if !self.selected_notes.remove(&note.id) {
+                                    self.selected_notes.insert(note.id);
+                                }
 
-                                        NoteOperationType::Move(
-                                            grab_offset - note.offset,
-                                            note.offset,
-                                            note.pitch,
-                                        )
-                                    }
-                                },
+                                return true;
+                            }
+
+                            if !self.selected_notes.contains(&note.id) {
+                                self.selected_notes.clear();
+                                self.selected_notes.insert(note.id);
+                            }
+
+                            let type_ = if mouse_x <= note.screen_x(self.zoom) + NOTE_EDGE_WIDTH {
+                                NoteOperationType::DragLeftEdge
+                            } else if mouse_x >= note.right_edge(self.zoom) - NOTE_EDGE_WIDTH {
+                                NoteOperationType::DragRightEdge
+                            } else {
+                                NoteOperationType::Move(
+                                    mouse_x_to_interval(mouse_x, self.project.grid, self.zoom)
+                                        - note.offset,
+                                )
+                            };
+
+                            let originals = track
+                                .notes
+                                .iter()
+                                .filter(|note| self.selected_notes.contains(&note.id))
+                                .cloned()
+                                .collect();
+
+                            self.mouse_operation = MouseOperation::NoteOperation {
+                                track_id: track.id,
+                                note_id: note.id,
+                                type_,
+                                originals,
+                            };
+                        } else if event.shift_key() {
+                            self.mouse_operation = MouseOperation::SelectRect {
+                                start: (mouse_x, mouse_y),
+                                end: (mouse_x, mouse_y),
                             };
                         } else {
-                            let len = track.notes.len();
+                            let note_id = self.project.new_note_id();
+                            let track = &mut self.project.tracks[track_index];
 
-                            track.notes.push(Note {
-                                pitch: mouse_y_to_pitch(mouse_y),
+                            let note = Note {
+                                id: note_id,
+                                pitch: mouse_y_to_pitch(mouse_y, self.zoom),
                                 velocity: 127,
-                                offset: mouse_x_to_interval(mouse_x),
+                                offset: mouse_x_to_interval(mouse_x, self.project.grid, self.zoom),
                                 length: self.last_placed_note_length,
-                            });
+                            };
+
+                            track.notes.push(note.clone());
+
+                            self.selected_notes.clear();
+                            self.selected_notes.insert(note_id);
 
                             self.mouse_operation = MouseOperation::NoteOperation {
-                                note_index: len,
+                                track_id: track.id,
+                                note_id,
                                 type_: NoteOperationType::CreateAndMove,
+                                originals: vec![note],
                             };
                         }
 
                         true
                     }
                     2 => {
-                        if let Some(note_index) = track.get_note_at_position(mouse_x, mouse_y) {
-                            self.perform_action(Action::DeleteNote(track_index, note_index));
+                        if let Some(note_index) =
+                            track.get_note_at_position(mouse_x, mouse_y, self.zoom)
+                        {
+                            let track_id = track.id;
+                            let note_id = track.notes[note_index].id;
+
+                            if self.selected_notes.contains(&note_id) {
+                                self.delete_selected_notes();
+                            } else {
+                                self.perform_action(Action::DeleteNote(track_id, note_id));
+                            }
+
                             true
                         } else {
                             false
@@ -302,112 +603,274 @@ impl Component for Model {
                     _ => false,
                 }
             }
-            Msg::PianoRollMouseUp => {
-                let selected_track_index = match self.selected_track_index {
+            Msg::VelocityLaneMouseDown(event) => {
+                match self.mouse_operation {
+                    MouseOperation::None => {}
+                    _ => return false,
+                };
+
+                let track_index = match self.selected_track_index {
                     None => return false,
                     Some(index) => index,
                 };
 
-                let result = match self.mouse_operation.clone() {
-                    MouseOperation::None | MouseOperation::DragProgressBar => false,
-                    MouseOperation::NoteOperation { note_index, type_ } => {
-                        let track = &mut self.project.tracks[selected_track_index];
-                        let note = &mut track.notes[note_index];
+                let track = &mut self.project.tracks[track_index];
 
-                        let new_offset = note.offset;
-                        let new_pitch = note.pitch;
-                        let new_length = note.length;
+                let (mouse_x, mouse_y) = relative_mouse_pos(&event);
 
-                        let mut create_note_instead = None;
+                if let Some(note_index) = track.get_note_at_velocity_bar(mouse_x, self.zoom) {
+                    let note = &mut track.notes[note_index];
 
-                        match type_ {
-                            NoteOperationType::DragLeftEdge(offset, length) => {
-                                note.offset = offset;
-                                note.length = length;
-                            }
-                            NoteOperationType::DragRightEdge(length) => {
-                                note.length = length;
-                            }
-                            NoteOperationType::Move(_, offset, pitch) => {
-                                note.offset = offset;
-                                note.pitch = pitch;
-                            }
-                            NoteOperationType::CreateAndMove => {
-                                let mut note = track.notes.remove(note_index);
-                                note.offset = new_offset;
-                                note.pitch = new_pitch;
-                                create_note_instead = Some(note);
-                            }
-                        }
+                    self.mouse_operation = MouseOperation::DragVelocity {
+                        track_id: track.id,
+                        note_id: note.id,
+                        old_velocity: note.velocity,
+                    };
 
-                        if let Some(note) = create_note_instead {
-                            self.perform_action(Action::CreateNote(selected_track_index, note));
-                        } else {
-                            self.perform_action(Action::EditNote(
-                                selected_track_index,
-                                note_index,
-                                new_offset,
-                                new_pitch,
-                                new_length,
-                            ));
-                        }
+                    note.velocity = mouse_y_to_velocity(mouse_y, VELOCITY_LANE_HEIGHT);
+
+                    true
+                } else {
+                    false
+                }
+            }
+            Msg::TempoLaneMouseDown(event) => {
+                match self.mouse_operation {
+                    MouseOperation::None => {}
+                    _ => return false,
+                };
+
+                let (mouse_x, mouse_y) = relative_mouse_pos(&event);
+
+                let tempo_map = &self.project.tempo_map;
+                let existing_event_index =
+                    tempo_map.get_event_at_position(mouse_x, mouse_y, TEMPO_LANE_HEIGHT, self.zoom);
+
+                match (event.buttons(), existing_event_index) {
+                    (1, Some(index)) if event.shift_key() => {
+                        let mut tempo_event = tempo_map.events[index].clone();
+                        tempo_event.ramp = !tempo_event.ramp;
+
+                        self.perform_action(Action::EditTempoEvent(index, tempo_event));
 
                         true
                     }
-                };
+                    (1, Some(index)) => {
+                        self.mouse_operation = MouseOperation::DragTempoEvent {
+                            index,
+                            old_event: tempo_map.events[index].clone(),
+                        };
+
+                        false
+                    }
+                    (1, None) => {
+                        let offset = mouse_x_to_interval(mouse_x, self.project.grid, self.zoom);
+
+                        if tempo_map
+                            .events
+                            .iter()
+                            .any(|tempo_event| (tempo_event.offset - offset).abs() < 1e-4)
+                        {
+                            return false;
+                        }
+
+                        self.perform_action(Action::CreateTempoEvent(TempoEvent {
+                            offset,
+                            bpm: mouse_y_to_bpm(mouse_y, TEMPO_LANE_HEIGHT),
+                            ramp: false,
+                        }));
 
-                self.mouse_operation = MouseOperation::None;
+                        true
+                    }
+                    // The initial tempo can't be removed.
+                    (2, Some(index)) if index > 0 => {
+                        self.perform_action(Action::DeleteTempoEvent(index));
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            Msg::AutomationLaneMouseDown(lane, event) => {
+                self.automation_lane_mouse_down(lane, &event)
+            }
+            Msg::SetNewControlLaneController(controller) => {
+                self.new_control_lane_controller = controller;
+                false
+            }
+            Msg::AddControlLane(controller) => {
+                self.add_control_lane(controller);
+                true
+            }
+            Msg::DeleteControlLane(controller) => {
+                if let Some(track_id) = self.selected_track_id() {
+                    self.perform_action(Action::DeleteControlLane(track_id, controller));
+                }
 
-                result
+                true
             }
+            Msg::MouseUp => self.mouse_up(),
             Msg::MouseMove(event) => {
                 let (mouse_x, mouse_y) = relative_mouse_pos(&event);
 
                 match self.mouse_operation.clone() {
+                    MouseOperation::DrawControl { .. } => self.draw_automation(mouse_x, mouse_y),
                     MouseOperation::DragProgressBar => {
                         self.set_play_offset_from_mouse_x(mouse_x);
                         true
                     }
-                    MouseOperation::NoteOperation { note_index, type_ } => {
-                        let index = match self.selected_track_index {
+                    MouseOperation::DragLoopMarker { end } => {
+                        let offset = (mouse_x - PIANO_KEYS_WIDTH) / self.zoom.whole_note_width();
+                        let offset = self.project.grid.snap(offset).max(0.0);
+                        let loop_region = &mut self.project.loop_region;
+
+                        if end {
+                            loop_region.end = offset.max(loop_region.start + MIN_INTERVAL);
+                        } else {
+                            loop_region.start = offset.min(loop_region.end - MIN_INTERVAL).max(0.0);
+                        }
+
+                        true
+                    }
+                    MouseOperation::DragTempoEvent { index, .. } => {
+                        let events = &mut self.project.tempo_map.events;
+
+                        // Events can't be dragged past their neighbours, and the first one stays put.
+                        let offset = if index == 0 {
+                            0.0
+                        } else {
+                            let min = events[index - 1].offset + MIN_INTERVAL;
+                            let max = events
+                                .get(index + 1)
+                                .map(|next| next.offset - MIN_INTERVAL)
+                                .unwrap_or(f64::INFINITY);
+
+                            mouse_x_to_interval(mouse_x, self.project.grid, self.zoom)
+                                .clamp(min, max.max(min))
+                        };
+
+                        let tempo_event = &mut events[index];
+                        tempo_event.offset = offset;
+                        tempo_event.bpm = mouse_y_to_bpm(mouse_y, TEMPO_LANE_HEIGHT);
+
+                        true
+                    }
+                    MouseOperation::DragVelocity {
+                        track_id, note_id, ..
+                    } => {
+                        let track = match self.project.track_index(track_id) {
+                            Some(index) => &mut self.project.tracks[index],
+                            None => return false,
+                        };
+
+                        let note = match track.note_index(note_id) {
+                            Some(index) => &mut track.notes[index],
+                            None => return false,
+                        };
+
+                        note.velocity = mouse_y_to_velocity(mouse_y, VELOCITY_LANE_HEIGHT);
+
+                        true
+                    }
+                    MouseOperation::NoteOperation {
+                        track_id,
+                        note_id,
+                        type_,
+                        originals,
+                    } => {
+                        let index = match self.project.track_index(track_id) {
                             Some(index) => index,
                             None => return false,
                         };
 
                         let track = &mut self.project.tracks[index];
-                        let note = &mut track.notes[note_index];
+                        let grabbed = originals
+                            .iter()
+                            .find(|note| note.id == note_id)
+                            .expect("the grabbed note is part of the operation");
 
-                        let offset = mouse_x_to_interval(mouse_x);
+                        let offset = mouse_x_to_interval(mouse_x, self.project.grid, self.zoom);
 
                         let pitch = 127
-                            - (mouse_y / NOTE_RECT_HEIGHT - 0.5).round().clamp(0.0, 127.0) as u8;
+                            - (mouse_y / self.zoom.note_height() - 0.5)
+                                .round()
+                                .clamp(0.0, 127.0) as u8;
+
+                        // Notes can only be edited by id, since they may be added and removed
+                        // while dragging.
+                        let mut edited_notes: Vec<(&mut Note, &Note)> = track
+                            .notes
+                            .iter_mut()
+                            .filter_map(|note| {
+                                let original =
+                                    originals.iter().find(|original| original.id == note.id)?;
+                                Some((note, original))
+                            })
+                            .collect();
 
                         match type_ {
-                            NoteOperationType::Move(grab_offset, _, _) => {
-                                note.offset = offset - grab_offset;
-                                note.pitch = pitch;
+                            NoteOperationType::Move(grab_offset) => {
+                                // Keep every note inside the piano roll.
+                                let min_offset = originals
+                                    .iter()
+                                    .map(|note| note.offset)
+                                    .fold(f64::INFINITY, f64::min);
+                                let min_pitch = originals.iter().map(|note| note.pitch).min();
+                                let max_pitch = originals.iter().map(|note| note.pitch).max();
+
+                                let offset_delta =
+                                    (offset - grab_offset - grabbed.offset).max(-min_offset);
+                                let pitch_delta = (pitch as i32 - grabbed.pitch as i32).clamp(
+                                    -(min_pitch.unwrap_or(0) as i32),
+                                    127 - max_pitch.unwrap_or(127) as i32,
+                                );
+
+                                for (note, original) in &mut edited_notes {
+                                    note.offset = original.offset + offset_delta;
+                                    note.pitch = (original.pitch as i32 + pitch_delta) as u8;
+                                }
                             }
                             NoteOperationType::CreateAndMove => {
-                                note.offset = offset;
-                                note.pitch = pitch;
+                                for (note, _) in &mut edited_notes {
+                                    note.offset = offset;
+                                    note.pitch = pitch;
+                                }
+                            }
+                            NoteOperationType::DragLeftEdge => {
+                                let offset_delta = offset - grabbed.offset;
+
+                                for (note, original) in &mut edited_notes {
+                                    let end = original.offset + original.length;
+
+                                    note.offset = (original.offset + offset_delta).min(end);
+                                    note.length = end - note.offset;
+                                }
                             }
-                            NoteOperationType::DragLeftEdge(_, _) => {
-                                let offset = offset.min(note.offset + note.length);
+                            NoteOperationType::DragRightEdge => {
+                                let length_delta = offset - grabbed.offset - grabbed.length;
 
-                                note.length += note.offset - offset;
-                                note.offset = offset;
+                                for (note, original) in &mut edited_notes {
+                                    note.length = (original.length + length_delta).max(0.0);
+                                }
                             }
-                            NoteOperationType::DragRightEdge(_) => {
-                                let offset = offset.max(note.offset - note.length);
-                                note.length = offset - note.offset;
+                        }
+
+                        for (note, _) in &mut edited_notes {
+                            if note.length < 1e-4 {
+                                note.length = MIN_INTERVAL;
                             }
                         }
 
-                        if note.length < 1e-4 {
-                            note.length = MIN_INTERVAL;
+                        if let Some(index) = track.note_index(note_id) {
+                            self.last_placed_note_length = track.notes[index].length;
                         }
 
-                        self.last_placed_note_length = note.length;
+                        true
+                    }
+                    MouseOperation::SelectRect { start, .. } => {
+                        self.mouse_operation = MouseOperation::SelectRect {
+                            start,
+                            end: (mouse_x, mouse_y),
+                        };
 
                         true
                     }
@@ -419,46 +882,47 @@ impl Component for Model {
                             };
 
                             let track = &mut self.project.tracks[selected_track_index];
-                            let note_index = track.get_note_at_position(mouse_x, mouse_y);
+                            let note_index =
+                                track.get_note_at_position(mouse_x, mouse_y, self.zoom);
 
                             if let Some(note_index) = note_index {
-                                self.perform_action(Action::DeleteNote(
-                                    selected_track_index,
-                                    note_index,
-                                ));
+                                let track_id = track.id;
+                                let note_id = track.notes[note_index].id;
+
+                                self.perform_action(Action::DeleteNote(track_id, note_id));
 
                                 return true;
                             }
                         }
 
                         self.act_on_selected_track(|track| {
-                            self.piano_roll_area
-                                .cast::<HtmlElement>()
-                                .map(|piano_roll_area| {
-                                    let mut cursor = "auto";
-
-                                    for note in &track.notes {
-                                        if mouse_x < note.screen_x()
-                                            || mouse_x > note.right_edge()
-                                            || mouse_y < note.screen_y()
-                                            || mouse_y > note.bottom_edge()
-                                        {
-                                            continue;
-                                        }
-
-                                        if mouse_x <= note.screen_x() + NOTE_EDGE_WIDTH
-                                            || mouse_x >= note.right_edge() - NOTE_EDGE_WIDTH
-                                        {
-                                            cursor = "ew-resize";
-                                            break;
-                                        } else {
-                                            cursor = "move";
-                                            break;
-                                        }
+                            if let Some(piano_roll_area) =
+                                self.piano_roll_area.cast::<HtmlElement>()
+                            {
+                                let mut cursor = "auto";
+
+                                for note in &track.notes {
+                                    if mouse_x < note.screen_x(self.zoom)
+                                        || mouse_x > note.right_edge(self.zoom)
+                                        || mouse_y < note.screen_y(self.zoom)
+                                        || mouse_y > note.bottom_edge(self.zoom)
+                                    {
+                                        continue;
                                     }
 
-                                    piano_roll_area.style().set_property("cursor", cursor).ok();
-                                });
+                                    if mouse_x <= note.screen_x(self.zoom) + NOTE_EDGE_WIDTH
+                                        || mouse_x >= note.right_edge(self.zoom) - NOTE_EDGE_WIDTH
+                                    {
+                                        cursor = "ew-resize";
+                                        break;
+                                    } else {
+                                        cursor = "move";
+                                        break;
+                                    }
+                                }
+
+                                piano_roll_area.style().set_property("cursor", cursor).ok();
+                            }
                         });
 
                         false
@@ -469,64 +933,117 @@ impl Component for Model {
                 self.play(ctx);
                 false
             }
-            Msg::SetPlayProgress(progress) => {
-                self.play_progress = progress;
+            Msg::ToggleRecording => {
+                self.toggle_recording(ctx);
                 true
             }
-            Msg::IncrementPlayProgress => {
-                self.play_progress += MIN_INTERVAL;
-
-                if self.play_offset + self.play_progress - 1e-4 >= self.project.length() {
-                    self.tick_interval.take().map(|interval| interval.cancel());
-                    self.play_progress = 0.0;
-                }
-
+            Msg::ToggleStepInput => {
+                self.toggle_step_input();
+                true
+            }
+            Msg::ToggleMusicalTyping => {
+                self.toggle_musical_typing();
+                true
+            }
+            Msg::StepInputRest => {
+                self.step_input_rest();
+                true
+            }
+            Msg::SetPlayProgress(progress) => {
+                self.play_progress = progress;
                 true
             }
-            Msg::PlayMidiNote(instrument, pitch) => {
-                self.play_midi_note(instrument, pitch, 1000.0);
+            // The progress line is moved directly, so only the end of playback needs a render.
+            Msg::PlaybackTick => self.playback_tick(),
+            Msg::PlayMidiNote(channel, instrument, pitch) => {
+                self.play_midi_note(channel, instrument, pitch, 1000.0);
                 false
             }
             Msg::ExportMidi => {
                 let project_name = self.project.name.to_owned();
 
-                web_sys::window()
-                    .and_then(|window| window.document())
-                    .map(|document| {
-                        document
-                            .create_element("a")
-                            .ok()
-                            .and_then(|anchor| anchor.dyn_into::<HtmlAnchorElement>().ok())
-                            .map(|anchor| {
-                                let midi_data = export_midi(&self.project);
+                let midi_data = export_midi(&self.project);
+                download_file(&midi_data, &format!("{}.mid", project_name));
 
-                                let array = Uint8Array::new_with_length(midi_data.len() as u32);
+                false
+            }
+            Msg::ImportMidi => {
+                let callback = ctx
+                    .link()
+                    .callback(|(name, data)| Msg::MidiFileLoaded(name, data));
 
-                                for (i, byte) in midi_data.iter().enumerate() {
-                                    array.set_index(i as u32, *byte);
-                                }
+                open_file_dialog(".mid,.midi", callback);
 
-                                Blob::new_with_u8_array_sequence(&Array::of1(&array))
-                                    .and_then(|blob| Url::create_object_url_with_blob(&blob))
-                                    .map(|href| {
-                                        document.body().map(|body| {
-                                            body.append_child(&anchor).unwrap();
+                false
+            }
+            Msg::MidiFileLoaded(file_name, data) => {
+                let name = file_name
+                    .rsplit_once('.')
+                    .map(|(stem, _)| stem)
+                    .unwrap_or(&file_name);
+
+                match import_midi(name, &data) {
+                    Ok(project) => {
+                        self.load_project(project);
+                        true
+                    }
+                    Err(error) => {
+                        alert(&format!("Failed to import {}: {}", file_name, error));
+                        false
+                    }
+                }
+            }
+            Msg::SaveProject => {
+                let data = save_project(&self.project);
 
-                                            anchor.set_href(&href);
-                                            anchor.set_download(&format!("{}.mid", project_name));
-                                            anchor.click();
+                download_file(
+                    data.as_bytes(),
+                    &format!("{}.{}", self.project.name, PROJECT_FILE_EXTENSION),
+                );
 
-                                            Url::revoke_object_url(&href).ok();
+                false
+            }
+            Msg::OpenProject => {
+                let callback = ctx
+                    .link()
+                    .callback(|(name, data)| Msg::ProjectFileLoaded(name, data));
 
-                                            body.remove_child(&anchor).unwrap();
-                                        });
-                                    })
-                                    .ok();
-                            });
-                    });
+                open_file_dialog(&format!(".{}", PROJECT_FILE_EXTENSION), callback);
 
                 false
             }
+            Msg::ProjectFileLoaded(file_name, data) => {
+                let result = String::from_utf8(data)
+                    .map_err(|_| "not a text file".to_string())
+                    .and_then(|data| load_project(&data).map_err(|error| error.to_string()));
+
+                match result {
+                    Ok(project) => {
+                        self.load_project(project);
+                        true
+                    }
+                    Err(error) => {
+                        alert(&format!("Failed to open {}: {}", file_name, error));
+                        false
+                    }
+                }
+            }
+            Msg::RestoreSession => {
+                self.restore_session();
+                true
+            }
+            Msg::SaveSession => {
+                self.save_session();
+                false
+            }
+            Msg::DiscardSession => {
+                self.recovered_session = None;
+                clear_session();
+
+                ctx.link().send_message(Msg::CreateTrack);
+
+                true
+            }
             Msg::Undo => {
                 self.undo_last();
                 true
@@ -535,19 +1052,139 @@ impl Component for Model {
                 self.redo_last();
                 true
             }
+            Msg::JumpToHistory(index) => {
+                self.jump_to_history(index);
+                true
+            }
+            Msg::SetQuantizeGrid(grid) => {
+                self.quantize_settings.grid = grid;
+                false
+            }
+            Msg::SetQuantizeStrength(strength) => {
+                self.quantize_settings.strength = strength;
+                false
+            }
+            Msg::SetQuantizeSwing(swing) => {
+                self.quantize_settings.swing = swing;
+                false
+            }
+            Msg::SetQuantizeEnds(quantize_ends) => {
+                self.quantize_settings.quantize_ends = quantize_ends;
+                false
+            }
+            Msg::Quantize => {
+                self.quantize_notes();
+                true
+            }
+            Msg::SetGrid(grid) => {
+                self.project.grid = grid;
+                self.autosave();
+                true
+            }
+            Msg::ZoomHorizontal(factor) => {
+                self.zoom.horizontal = (self.zoom.horizontal * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+                true
+            }
+            Msg::ZoomVertical(factor) => {
+                self.zoom.vertical = (self.zoom.vertical * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+                true
+            }
+            Msg::PianoRollWheel(event) => {
+                if !event.ctrl_key() {
+                    return false;
+                }
+
+                event.prevent_default();
+
+                // Some browsers report shift+wheel as horizontal scrolling.
+                let delta = event.delta_y() + event.delta_x();
+
+                if delta == 0.0 {
+                    return false;
+                }
+
+                let factor = if delta < 0.0 {
+                    ZOOM_STEP
+                } else {
+                    1.0 / ZOOM_STEP
+                };
+
+                if event.shift_key() {
+                    ctx.link().send_message(Msg::ZoomVertical(factor));
+                } else {
+                    ctx.link().send_message(Msg::ZoomHorizontal(factor));
+                }
+
+                false
+            }
+            Msg::KeyDown(event) => {
+                if is_text_field(&event) {
+                    return false;
+                }
+
+                if self.musical_typing_key_down(&event) {
+                    event.prevent_default();
+                    return true;
+                }
+
+                match event.key().as_str() {
+                    "Backspace" if self.step_input.is_some() => {
+                        self.step_input_back();
+                        true
+                    }
+                    " " if self.step_input.is_some() => {
+                        event.prevent_default();
+                        self.step_input_rest();
+                        true
+                    }
+                    // Notes being dragged are edited again once the mouse is released.
+                    "Delete" | "Backspace" if self.mouse_operation.is_none() => {
+                        self.delete_selected_notes();
+                        true
+                    }
+                    "Escape" => {
+                        self.selected_notes.clear();
+                        true
+                    }
+                    "a" | "A" if event.ctrl_key() || event.meta_key() => {
+                        event.prevent_default();
+
+                        if let Some(track_index) = self.selected_track_index {
+                            let notes = &self.project.tracks[track_index].notes;
+                            self.selected_notes = notes.iter().map(|note| note.id).collect();
+                        }
+
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            Msg::KeyUp(event) => {
+                if is_text_field(&event) {
+                    return false;
+                }
+
+                self.musical_typing_key_up(&event)
+            }
+            Msg::MainViewBlur => {
+                self.release_typed_notes();
+                true
+            }
+            Msg::Copy(event) => {
+                self.copy_selected_notes(&event);
+                false
+            }
+            Msg::Cut(event) => self.cut_selected_notes(&event),
+            Msg::Paste(event) => self.paste_notes(&event),
+            Msg::EndContinuousEdit => {
+                self.last_continuous_edit = None;
+                false
+            }
         }
     }
 
     fn rendered(&mut self, _ctx: &Context<Self>, _first_render: bool) {
-        self.progress_line
-            .cast::<SvgLineElement>()
-            .map(|progress_line| {
-                let x = (self.play_offset + self.play_progress) * WHOLE_NOTE_WIDTH;
-
-                for animated_length in [progress_line.x1(), progress_line.x2()] {
-                    animated_length.base_val().set_value(x as f32).ok();
-                }
-            });
+        self.update_progress_line();
     }
 
     fn view(&self, ctx: &Context<Self>) -> Html {
@@ -560,17 +1197,176 @@ impl Component for Model {
 }
 
 impl Model {
+    pub fn selected_track_id(&self) -> Option<TrackId> {
+        self.act_on_selected_track(|track| track.id)
+    }
+
     pub fn act_on_selected_track<R>(&self, action: impl Fn(&Track) -> R) -> Option<R> {
-        if let Some(index) = self.selected_track_index {
-            Some(action(&self.project.tracks[index]))
-        } else {
+        self.selected_track_index
+            .map(|index| action(&self.project.tracks[index]))
+    }
+
+    /// Replace the current project, discarding the undo history.
+    fn load_project(&mut self, project: Project) {
+        self.stop_playback();
+
+        self.selected_track_index = if project.tracks.is_empty() {
             None
+        } else {
+            Some(0)
+        };
+
+        self.project = project;
+        self.selected_notes.clear();
+        self.mouse_operation = MouseOperation::None;
+        self.history = UndoTree::default();
+        self.last_continuous_edit = None;
+        self.play_offset = 0.0;
+        self.play_progress = 0.0;
+
+        self.autosave();
+    }
+
+    /// Delete the selected notes as a single undo step.
+    fn delete_selected_notes(&mut self) {
+        let track = match self.selected_track_index {
+            None => return,
+            Some(index) => &self.project.tracks[index],
+        };
+
+        let deletions: Vec<Action> = track
+            .notes
+            .iter()
+            .filter(|note| self.selected_notes.contains(&note.id))
+            .map(|note| Action::DeleteNote(track.id, note.id))
+            .collect();
+
+        self.begin_transaction();
+
+        for deletion in deletions {
+            self.perform_action(deletion);
         }
+
+        self.commit_transaction();
+        self.selected_notes.clear();
     }
 
     fn set_play_offset_from_mouse_x(&mut self, mouse_x: f64) {
-        let offset = (mouse_x - PIANO_KEYS_WIDTH) / WHOLE_NOTE_WIDTH;
-        self.play_offset = snap(offset, MIN_INTERVAL);
+        let offset = (mouse_x - PIANO_KEYS_WIDTH) / self.zoom.whole_note_width();
+        self.play_offset = self.project.grid.snap(offset);
+    }
+
+    /// End the mouse operation, wherever the mouse was released. Edits made while dragging are
+    /// undone and performed again as actions, so they can be undone and get saved.
+    fn mouse_up(&mut self) -> bool {
+        match std::mem::replace(&mut self.mouse_operation, MouseOperation::None) {
+            MouseOperation::None | MouseOperation::DragProgressBar => false,
+            MouseOperation::DragLoopMarker { .. } => {
+                self.autosave();
+                false
+            }
+            MouseOperation::NoteOperation {
+                track_id,
+                note_id,
+                type_,
+                originals,
+            } => {
+                // The track may have been deleted while dragging.
+                let track_index = match self.project.track_index(track_id) {
+                    Some(index) => index,
+                    None => return true,
+                };
+
+                let track = &mut self.project.tracks[track_index];
+
+                if let NoteOperationType::CreateAndMove = type_ {
+                    if let Some(index) = track.note_index(note_id) {
+                        let note = track.notes.remove(index);
+                        self.perform_action(Action::CreateNote(track_id, index, note));
+                    }
+                } else {
+                    let mut edits = Vec::new();
+
+                    for original in originals {
+                        let note = match track.note_index(original.id) {
+                            Some(index) => &mut track.notes[index],
+                            None => continue,
+                        };
+
+                        if note.offset != original.offset
+                            || note.pitch != original.pitch
+                            || note.length != original.length
+                        {
+                            edits.push(Action::EditNote(
+                                track_id,
+                                note.id,
+                                note.offset,
+                                note.pitch,
+                                note.length,
+                            ));
+
+                            *note = original;
+                        }
+                    }
+
+                    self.begin_transaction();
+
+                    for edit in edits {
+                        self.perform_action(edit);
+                    }
+
+                    self.commit_transaction();
+                }
+
+                true
+            }
+            MouseOperation::SelectRect { start, end } => {
+                if let Some(index) = self.selected_track_index {
+                    let track = &self.project.tracks[index];
+                    self.selected_notes
+                        .extend(track.get_notes_in_rect(start, end, self.zoom));
+                }
+
+                true
+            }
+            MouseOperation::DragVelocity {
+                track_id,
+                note_id,
+                old_velocity,
+            } => {
+                // The track, or the note, may have been deleted while dragging.
+                let track = match self.project.track_index(track_id) {
+                    Some(index) => &mut self.project.tracks[index],
+                    None => return true,
+                };
+
+                let note = match track.note_index(note_id) {
+                    Some(index) => &mut track.notes[index],
+                    None => return true,
+                };
+
+                let new_velocity = note.velocity;
+                note.velocity = old_velocity;
+
+                self.perform_action(Action::EditNoteVelocity(track_id, note_id, new_velocity));
+
+                true
+            }
+            MouseOperation::DragTempoEvent { index, old_event } => {
+                let events = &mut self.project.tempo_map.events;
+                let new_event = std::mem::replace(&mut events[index], old_event);
+
+                self.perform_action(Action::EditTempoEvent(index, new_event));
+
+                true
+            }
+            MouseOperation::DrawControl {
+                track_id,
+                lane,
+                originals,
+                ..
+            } => self.finish_drawing_automation(track_id, lane, originals),
+        }
     }
 }
 
@@ -578,17 +1374,59 @@ impl Model {
 enum MouseOperation {
     None,
     DragProgressBar,
+    DragLoopMarker {
+        /// Whether this is the end marker rather than the start marker.
+        end: bool,
+    },
+    /// Edit the selected notes by dragging one of them.
     NoteOperation {
-        note_index: usize,
+        track_id: TrackId,
+        /// The note being dragged.
+        note_id: NoteId,
         type_: NoteOperationType,
+        /// The notes being edited, as they were before the drag.
+        originals: Vec<Note>,
+    },
+    /// Rubber-band selection, in piano roll coordinates.
+    SelectRect {
+        start: (f64, f64),
+        end: (f64, f64),
+    },
+    DragVelocity {
+        track_id: TrackId,
+        note_id: NoteId,
+        old_velocity: u8,
     },
+    /// Draw into an automation lane, freehand or as a straight line from `start`. Points are
+    /// kept as offsets and values, whichever kind of points the lane holds.
+    DrawControl {
+        track_id: TrackId,
+        lane: AutomationLane,
+        line: bool,
+        start: (f64, f64),
+        /// Where the mouse was last, for filling the gaps while drawing freehand.
+        last: (f64, f64),
+        /// The points of the lane before drawing.
+        originals: Vec<(f64, f64)>,
+    },
+    DragTempoEvent {
+        index: usize,
+        old_event: TempoEvent,
+    },
+}
+
+impl MouseOperation {
+    fn is_none(&self) -> bool {
+        matches!(self, Self::None)
+    }
 }
 
 #[derive(Clone)]
 enum NoteOperationType {
-    DragLeftEdge(f64, f64),
-    DragRightEdge(f64),
-    Move(f64, f64, u8),
+    DragLeftEdge,
+    DragRightEdge,
+    /// Move with the cursor, at the given offset from the grabbed note's start.
+    Move(f64),
     CreateAndMove,
 }
 
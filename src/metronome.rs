@@ -0,0 +1,128 @@
+use crate::{
+    midi::{MidiMessage, MidiMessageType},
+    project::{TimeSignature, DRUM_CHANNEL},
+    Model,
+};
+
+/// GM percussion notes played by default: hi and low wood block.
+const DEFAULT_ACCENT_PITCH: u8 = 76;
+const DEFAULT_PITCH: u8 = 77;
+const ACCENT_VELOCITY: u8 = 127;
+const VELOCITY: u8 = 90;
+/// Length of a click in whole notes.
+const CLICK_LENGTH: f64 = 1.0 / 32.0;
+/// Longest count-in, in measures.
+pub const MAX_COUNT_IN: u32 = 2;
+
+pub struct MetronomeSettings {
+    /// Click along with playback.
+    pub enabled: bool,
+    pub channel: u8,
+    /// Played on the first beat of every measure.
+    pub accent_pitch: u8,
+    /// Played on the other beats.
+    pub pitch: u8,
+    /// Measures clicked before playback starts, up to `MAX_COUNT_IN`.
+    pub count_in: u32,
+}
+
+impl Default for MetronomeSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            channel: DRUM_CHANNEL,
+            accent_pitch: DEFAULT_ACCENT_PITCH,
+            pitch: DEFAULT_PITCH,
+            count_in: 0,
+        }
+    }
+}
+
+impl MetronomeSettings {
+    /// Note on and note off of a click.
+    fn click(&self, accent: bool) -> (MidiMessageType, MidiMessageType) {
+        let (pitch, velocity) = if accent {
+            (self.accent_pitch, ACCENT_VELOCITY)
+        } else {
+            (self.pitch, VELOCITY)
+        };
+
+        (
+            MidiMessageType::NoteOn(self.channel, pitch, velocity),
+            MidiMessageType::NoteOff(self.channel, pitch, velocity),
+        )
+    }
+}
+
+/// Length of a click in whole notes, keeping clicks apart in very short beats.
+fn click_length(time_signature: &TimeSignature) -> f64 {
+    CLICK_LENGTH.min(0.5 / time_signature.bottom as f64)
+}
+
+impl Model {
+    /// Clicks on every beat from `start` up to `end`, if the metronome is enabled.
+    pub fn metronome_messages(&self, start: f64, end: f64) -> Vec<MidiMessage> {
+        let settings = &self.metronome;
+        let mut messages = Vec::new();
+
+        if !settings.enabled {
+            return messages;
+        }
+
+        for measure in self.project.time_signature_map.measures(end) {
+            let time_signature = measure.time_signature;
+
+            for beat in 0..time_signature.top {
+                let offset = measure.offset + beat as f64 / time_signature.bottom as f64;
+
+                if offset >= end {
+                    break;
+                }
+
+                if offset < start {
+                    continue;
+                }
+
+                let (note_on, note_off) = settings.click(beat == 0);
+
+                messages.push(MidiMessage {
+                    offset,
+                    type_: note_on,
+                });
+                messages.push(MidiMessage {
+                    offset: offset + click_length(time_signature),
+                    type_: note_off,
+                });
+            }
+        }
+
+        messages
+    }
+
+    /// Clicks counting in to playback from `offset`, in the time signature and tempo found
+    /// there, with timestamps relative to the start of the count-in. Also returns how long the
+    /// count-in lasts in milliseconds.
+    pub fn count_in_messages(&self, offset: f64) -> (Vec<(f64, MidiMessageType)>, f64) {
+        let settings = &self.metronome;
+        let time_signature = self.project.time_signature_map.time_signature_at(offset);
+
+        let whole_note_duration = 240_000.0 / self.project.tempo_map.bpm_at(offset);
+        let beat_duration = whole_note_duration / time_signature.bottom as f64;
+        let beats = settings.count_in * time_signature.top;
+
+        let mut messages = Vec::new();
+
+        for beat in 0..beats {
+            let time = beat as f64 * beat_duration;
+            let (note_on, note_off) = settings.click(beat % time_signature.top == 0);
+
+            messages.push((time, note_on));
+            messages.push((
+                time + click_length(time_signature) * whole_note_duration,
+                note_off,
+            ));
+        }
+
+        (messages, beats as f64 * beat_duration)
+    }
+}
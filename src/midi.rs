@@ -1,7 +1,18 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
 use js_sys::Array;
 use wasm_bindgen::JsValue;
 
-use crate::project::Project;
+use crate::{
+    automation::is_automatable,
+    project::{
+        BendPoint, ControlLane, ControlPoint, Note, NoteId, Project, TempoEvent, TimeSignature,
+        TimeSignatureChange, Track, DEFAULT_BEND_RANGE, MAX_BEND_RANGE, MIN_BEND,
+    },
+};
 
 pub struct MidiMessage {
     // Offset in whole notes.
@@ -9,18 +20,30 @@ pub struct MidiMessage {
     pub type_: MidiMessageType,
 }
 
+/// Channel messages; the first field is always the zero-based MIDI channel.
 pub enum MidiMessageType {
-    ChangeInstrument(u8),
-    NoteOn(u8, u8),
-    NoteOff(u8, u8),
+    ChangeInstrument(u8, u8),
+    NoteOn(u8, u8, u8),
+    NoteOff(u8, u8, u8),
+    /// Channel, controller number and value.
+    ControlChange(u8, u8, u8),
+    /// Channel and bend, from `MIN_BEND` to `MAX_BEND`.
+    PitchBend(u8, i16),
 }
 
 impl MidiMessageType {
     pub fn to_bytes(&self) -> Vec<u8> {
         match self {
-            Self::ChangeInstrument(instrument) => vec![0xC0, *instrument],
-            Self::NoteOn(pitch, velocity) => vec![0x90, *pitch, *velocity],
-            Self::NoteOff(pitch, velocity) => vec![0x80, *pitch, *velocity],
+            Self::ChangeInstrument(channel, instrument) => vec![0xC0 | channel, *instrument],
+            Self::NoteOn(channel, pitch, velocity) => vec![0x90 | channel, *pitch, *velocity],
+            Self::NoteOff(channel, pitch, velocity) => vec![0x80 | channel, *pitch, *velocity],
+            Self::ControlChange(channel, controller, value) => {
+                vec![0xB0 | channel, *controller, *value]
+            }
+            Self::PitchBend(channel, value) => {
+                let value = (*value - MIN_BEND) as u16;
+                vec![0xE0 | channel, (value & 0x7f) as u8, (value >> 7) as u8]
+            }
         }
     }
 
@@ -38,32 +61,148 @@ impl MidiMessageType {
     }
 }
 
-impl Project {
+impl MidiMessage {
+    // Order of simultaneous messages: release notes before instruments change and new notes start.
+    fn priority(&self) -> u8 {
+        match self.type_ {
+            MidiMessageType::NoteOff(_, _, _) => 0,
+            MidiMessageType::ChangeInstrument(_, _) => 1,
+            MidiMessageType::ControlChange(_, _, _) | MidiMessageType::PitchBend(_, _) => 2,
+            MidiMessageType::NoteOn(_, _, _) => 3,
+        }
+    }
+}
+
+pub fn sort_messages(messages: &mut [MidiMessage]) {
+    messages.sort_by(|a, b| {
+        a.offset
+            .partial_cmp(&b.offset)
+            .unwrap()
+            .then(a.priority().cmp(&b.priority()))
+    });
+}
+
+/// Controllers selecting registered parameter 0, the pitch bend range.
+const RPN_MSB_CONTROLLER: u8 = 101;
+const RPN_LSB_CONTROLLER: u8 = 100;
+const DATA_ENTRY_MSB_CONTROLLER: u8 = 6;
+const DATA_ENTRY_LSB_CONTROLLER: u8 = 38;
+/// Selected as the parameter afterwards, so stray data entry doesn't change the range.
+const NULL_RPN: u8 = 127;
+
+/// Interpolated pitch bend is sent at most this often, in whole notes...
+const PITCH_BEND_STEP: f64 = 1.0 / 128.0;
+/// ...and only once it changed by this much.
+const MIN_PITCH_BEND_CHANGE: i16 = 64;
+
+impl Track {
+    /// Messages setting the synthesizer's pitch bend range to the track's.
+    pub fn bend_range_messages(&self) -> Vec<MidiMessageType> {
+        [
+            (RPN_MSB_CONTROLLER, 0),
+            (RPN_LSB_CONTROLLER, 0),
+            (DATA_ENTRY_MSB_CONTROLLER, self.bend_range),
+            (DATA_ENTRY_LSB_CONTROLLER, 0),
+            (RPN_MSB_CONTROLLER, NULL_RPN),
+            (RPN_LSB_CONTROLLER, NULL_RPN),
+        ]
+        .into_iter()
+        .map(|(controller, value)| MidiMessageType::ControlChange(self.channel, controller, value))
+        .collect()
+    }
+
+    /// The pitch bend curve as messages, interpolated between its points.
+    fn pitch_bend_messages(&self) -> Vec<MidiMessage> {
+        let mut messages = Vec::new();
+
+        for (index, point) in self.pitch_bend.iter().enumerate() {
+            messages.push(MidiMessage {
+                offset: point.offset,
+                type_: MidiMessageType::PitchBend(self.channel, point.value),
+            });
+
+            let next = match self.pitch_bend.get(index + 1) {
+                Some(next) => next,
+                None => break,
+            };
+
+            let mut last_value = point.value;
+            let mut offset = point.offset + PITCH_BEND_STEP;
+
+            while offset < next.offset - 1e-6 {
+                let value = self.pitch_bend_at(offset).unwrap_or(last_value);
+
+                if (value - last_value).abs() >= MIN_PITCH_BEND_CHANGE {
+                    messages.push(MidiMessage {
+                        offset,
+                        type_: MidiMessageType::PitchBend(self.channel, value),
+                    });
+
+                    last_value = value;
+                }
+
+                offset += PITCH_BEND_STEP;
+            }
+        }
+
+        messages
+    }
+
     pub fn to_midi(&self) -> Vec<MidiMessage> {
         let mut messages = Vec::new();
 
-        let full_velocity = 0x7f;
+        for note in &self.notes {
+            messages.push(MidiMessage {
+                offset: note.offset,
+                type_: MidiMessageType::NoteOn(self.channel, note.pitch, note.velocity),
+            });
 
-        for track in &self.tracks {
-            for note in &track.notes {
-                messages.push(MidiMessage {
-                    offset: note.offset,
-                    type_: MidiMessageType::ChangeInstrument(track.instrument),
-                });
+            messages.push(MidiMessage {
+                offset: note.offset + note.length,
+                type_: MidiMessageType::NoteOff(self.channel, note.pitch, note.velocity),
+            });
+        }
 
+        for lane in &self.control_lanes {
+            for point in &lane.points {
                 messages.push(MidiMessage {
-                    offset: note.offset,
-                    type_: MidiMessageType::NoteOn(note.pitch, full_velocity),
+                    offset: point.offset,
+                    type_: MidiMessageType::ControlChange(
+                        self.channel,
+                        lane.controller,
+                        point.value,
+                    ),
                 });
+            }
+        }
 
-                messages.push(MidiMessage {
-                    offset: note.offset + note.length,
-                    type_: MidiMessageType::NoteOff(note.pitch, full_velocity),
-                });
+        messages.append(&mut self.pitch_bend_messages());
+
+        sort_messages(&mut messages);
+
+        messages
+    }
+}
+
+impl Project {
+    pub fn to_midi(&self) -> Vec<MidiMessage> {
+        let mut messages = Vec::new();
+
+        for track in &self.tracks {
+            for message in track.to_midi() {
+                // Tracks may share a channel, so restore the instrument before every note.
+                if let MidiMessageType::NoteOn(_, _, _) = message.type_ {
+                    messages.push(MidiMessage {
+                        offset: message.offset,
+                        type_: MidiMessageType::ChangeInstrument(track.channel, track.instrument),
+                    });
+                }
+
+                messages.push(message);
             }
         }
 
-        messages.sort_by(|a, b| a.offset.partial_cmp(&b.offset).unwrap());
+        sort_messages(&mut messages);
 
         messages
     }
@@ -82,62 +221,856 @@ fn to_varlen(value: u32) -> Vec<u8> {
     bytes
 }
 
-pub fn export_midi(project: &Project) -> Vec<u8> {
-    let messages = project.to_midi();
+const TICKS_PER_QUARTER_NOTE: u16 = 1024;
+const TICKS_PER_WHOLE_NOTE: f64 = TICKS_PER_QUARTER_NOTE as f64 * 4.0;
+
+/// Accumulates the events of a single MTrk chunk.
+struct TrackWriter {
+    bytes: Vec<u8>,
+    last_tick: u32,
+}
+
+impl TrackWriter {
+    fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            last_tick: 0,
+        }
+    }
+
+    fn event(&mut self, offset: f64, data: &[u8]) {
+        // Deltas are computed from absolute ticks so rounding errors don't accumulate.
+        let tick = ((offset * TICKS_PER_WHOLE_NOTE).round() as u32).max(self.last_tick);
+
+        self.bytes.append(&mut to_varlen(tick - self.last_tick));
+        self.bytes.extend_from_slice(data);
+
+        self.last_tick = tick;
+    }
+
+    fn meta(&mut self, offset: f64, type_: u8, data: &[u8]) {
+        let mut event = vec![0xFF, type_];
+        event.append(&mut to_varlen(data.len() as u32));
+        event.extend_from_slice(data);
+
+        self.event(offset, &event);
+    }
+
+    fn finish(mut self, bytes: &mut Vec<u8>) {
+        self.meta(0.0, 0x2F, &[]);
+        write_chunk(bytes, b"MTrk", &self.bytes);
+    }
+}
+
+const TEMPO_RAMP_STEP: f64 = 1.0 / 32.0;
+
+// Set Tempo data: microseconds per quarter note as a 24-bit integer.
+fn tempo_bytes(bpm: f64) -> [u8; 3] {
+    let tempo = ((60_000_000.0 / bpm).round() as u32).min(0xFF_FFFF);
+    let bytes = tempo.to_be_bytes();
+
+    [bytes[1], bytes[2], bytes[3]]
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    bytes.extend_from_slice(chunk_type);
+    bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(data);
+}
 
+pub fn export_midi(project: &Project) -> Vec<u8> {
     let mut bytes = Vec::new();
 
-    for byte in "MThd".bytes() {
-        bytes.push(byte);
+    let mut header = Vec::new();
+
+    // Format 1: a conductor track followed by one MTrk per project track.
+    header.extend_from_slice(&1u16.to_be_bytes());
+    header.extend_from_slice(&(project.tracks.len() as u16 + 1).to_be_bytes());
+    header.extend_from_slice(&TICKS_PER_QUARTER_NOTE.to_be_bytes());
+
+    write_chunk(&mut bytes, b"MThd", &header);
+
+    let mut conductor = TrackWriter::new();
+
+    conductor.meta(0.0, 0x03, project.name.as_bytes());
+
+    // Meta events are interleaved by offset, so collect them before writing.
+    let mut meta_events = Vec::new();
+
+    let time_signature_map = &project.time_signature_map;
+
+    for change in &time_signature_map.changes {
+        let time_signature = &change.time_signature;
+
+        meta_events.push((
+            time_signature_map.measure_offset(change.measure),
+            0x58,
+            vec![
+                time_signature.top as u8,
+                time_signature.bottom.trailing_zeros() as u8,
+                24,
+                8,
+            ],
+        ));
     }
 
-    for byte in 6u32.to_be_bytes() {
-        bytes.push(byte);
+    let events = &project.tempo_map.events;
+
+    for (index, event) in events.iter().enumerate() {
+        meta_events.push((event.offset, 0x51, tempo_bytes(event.bpm).to_vec()));
+
+        // SMF has no notion of tempo ramps, so approximate them with small steps.
+        if let Some(next) = events.get(index + 1).filter(|_| event.ramp) {
+            let mut offset = event.offset + TEMPO_RAMP_STEP;
+
+            while offset < next.offset - 1e-6 {
+                let bpm = project.tempo_map.bpm_at(offset);
+                meta_events.push((offset, 0x51, tempo_bytes(bpm).to_vec()));
+
+                offset += TEMPO_RAMP_STEP;
+            }
+        }
     }
 
-    for byte in 0u16.to_be_bytes() {
-        bytes.push(byte);
+    meta_events.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    for (offset, type_, data) in meta_events {
+        conductor.meta(offset, type_, &data);
     }
 
-    for byte in 1u16.to_be_bytes() {
-        bytes.push(byte);
+    conductor.finish(&mut bytes);
+
+    for track in &project.tracks {
+        let mut writer = TrackWriter::new();
+
+        writer.meta(0.0, 0x03, track.name.as_bytes());
+        writer.event(
+            0.0,
+            &MidiMessageType::ChangeInstrument(track.channel, track.instrument).to_bytes(),
+        );
+
+        for message in track.bend_range_messages() {
+            writer.event(0.0, &message.to_bytes());
+        }
+
+        for message in track.to_midi() {
+            writer.event(message.offset, &message.type_.to_bytes());
+        }
+
+        writer.finish(&mut bytes);
     }
 
-    let ticks_per_quarter_note = 1024u16;
-    let delta_multiplier = (480.0 / project.bpm * ticks_per_quarter_note as f64).round() as u32;
+    bytes
+}
 
-    for byte in ticks_per_quarter_note.to_be_bytes() {
-        bytes.push(byte);
+#[derive(Debug)]
+pub enum ImportError {
+    UnexpectedEnd,
+    NotAMidiFile,
+    UnsupportedFormat(u16),
+    UnsupportedTimeDivision,
+    MissingStatus,
+    InvalidStatus(u8),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnexpectedEnd => write!(f, "unexpected end of file"),
+            Self::NotAMidiFile => write!(f, "not a Standard MIDI File"),
+            Self::UnsupportedFormat(format) => write!(f, "unsupported SMF format {}", format),
+            Self::UnsupportedTimeDivision => write!(f, "SMPTE time division is not supported"),
+            Self::MissingStatus => write!(f, "data byte without a running status"),
+            Self::InvalidStatus(status) => write!(f, "invalid status byte {:#04X}", status),
+        }
     }
+}
 
-    for byte in "MTrk".bytes() {
-        bytes.push(byte);
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
     }
 
-    let mut track_bytes = Vec::new();
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
 
-    let mut last_offset = 0.0;
+    fn peek_u8(&self) -> Result<u8, ImportError> {
+        self.data
+            .get(self.position)
+            .copied()
+            .ok_or(ImportError::UnexpectedEnd)
+    }
 
-    for message in messages {
-        let delta = message.offset - last_offset;
-        last_offset = message.offset;
+    fn read_u8(&mut self) -> Result<u8, ImportError> {
+        let byte = self.peek_u8()?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], ImportError> {
+        // Lengths come from the file, so may be anything up to u32::MAX.
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|end| *end <= self.data.len())
+            .ok_or(ImportError::UnexpectedEnd)?;
 
-        let delta = delta * delta_multiplier as f64;
-        track_bytes.append(&mut to_varlen(delta as _));
+        let bytes = &self.data[self.position..end];
+        self.position = end;
 
-        track_bytes.append(&mut message.type_.to_bytes());
+        Ok(bytes)
     }
 
-    for byte in (track_bytes.len() as u32).to_be_bytes() {
-        bytes.push(byte);
+    fn read_u16(&mut self) -> Result<u16, ImportError> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
     }
 
-    track_bytes.push(0);
-    track_bytes.push(0xFF);
-    track_bytes.push(0x2F);
-    track_bytes.push(0);
+    fn read_u32(&mut self) -> Result<u32, ImportError> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
 
-    bytes.append(&mut track_bytes);
+    fn read_varlen(&mut self) -> Result<u32, ImportError> {
+        let mut value = 0u32;
 
-    bytes
+        // Variable-length quantities are at most four bytes long.
+        for _ in 0..4 {
+            let byte = self.read_u8()?;
+            value = (value << 7) | (byte & 0x7f) as u32;
+
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+
+        Ok(value)
+    }
+}
+
+#[derive(Default)]
+struct ImportedChannel {
+    instrument: Option<u8>,
+    notes: Vec<Note>,
+    control_lanes: BTreeMap<u8, Vec<ControlPoint>>,
+    pitch_bend: Vec<BendPoint>,
+    bend_range: Option<u8>,
+    /// Registered parameter selected for data entry, most significant byte first.
+    rpn: [Option<u8>; 2],
+    // Start tick and velocity of the notes still held, per pitch.
+    held_notes: HashMap<u8, Vec<(u64, u8)>>,
+}
+
+impl ImportedChannel {
+    fn note_on(&mut self, tick: u64, pitch: u8, velocity: u8) {
+        self.held_notes
+            .entry(pitch)
+            .or_default()
+            .push((tick, velocity));
+    }
+
+    fn note_off(&mut self, tick: u64, pitch: u8, ticks_per_whole_note: f64) {
+        let held = match self.held_notes.get_mut(&pitch) {
+            Some(held) if !held.is_empty() => held,
+            _ => return,
+        };
+
+        let (start, velocity) = held.remove(0);
+
+        self.notes.push(Note {
+            // Real IDs are assigned once the notes are added to the project.
+            id: NoteId(0),
+            pitch,
+            velocity,
+            offset: start as f64 / ticks_per_whole_note,
+            // Drum parts often end notes on the tick they start, but notes need a length to
+            // sound at all, and to be saved.
+            length: (tick - start).max(1) as f64 / ticks_per_whole_note,
+        });
+    }
+
+    fn control_change(&mut self, offset: f64, controller: u8, value: u8) {
+        match controller {
+            RPN_MSB_CONTROLLER => self.rpn[0] = Some(value),
+            RPN_LSB_CONTROLLER => self.rpn[1] = Some(value),
+            DATA_ENTRY_MSB_CONTROLLER if self.rpn == [Some(0), Some(0)] => {
+                self.bend_range = Some(value.min(MAX_BEND_RANGE));
+            }
+            _ if is_automatable(controller) => {
+                self.control_lanes
+                    .entry(controller)
+                    .or_default()
+                    .push(ControlPoint { offset, value });
+            }
+            _ => {}
+        }
+    }
+
+    /// Bends hold until the next message, while the curve ramps between its points, so a point
+    /// holding the previous value goes a tick before every change.
+    fn pitch_bend(&mut self, tick: u64, value: i16, ticks_per_whole_note: f64) {
+        let offset = tick as f64 / ticks_per_whole_note;
+
+        if let Some(&last) = self.pitch_bend.last() {
+            if last.value == value {
+                return;
+            }
+
+            let hold_offset = tick.saturating_sub(1) as f64 / ticks_per_whole_note;
+
+            if hold_offset > last.offset {
+                self.pitch_bend.push(BendPoint {
+                    offset: hold_offset,
+                    value: last.value,
+                });
+            }
+        }
+
+        self.pitch_bend.push(BendPoint { offset, value });
+    }
+
+    /// Whether the channel sets anything up to keep, even without notes.
+    fn has_setup(&self) -> bool {
+        !self.control_lanes.is_empty()
+            || !self.pitch_bend.is_empty()
+            || self.bend_range.is_some()
+            || self.instrument.is_some()
+    }
+
+    fn release_all(&mut self, tick: u64, ticks_per_whole_note: f64) {
+        let mut pitches: Vec<u8> = self.held_notes.keys().copied().collect();
+        pitches.sort_unstable();
+
+        for pitch in pitches {
+            while !self.held_notes[&pitch].is_empty() {
+                self.note_off(tick, pitch, ticks_per_whole_note);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct ImportedTrack {
+    name: Option<String>,
+    channels: BTreeMap<u8, ImportedChannel>,
+}
+
+/// Add a track for the channel, named after the track it came from. The channel number is
+/// added to the name if the track used more than one channel.
+fn add_track(
+    project: &mut Project,
+    name: Option<String>,
+    channel_number: u8,
+    show_channel: bool,
+    channel: ImportedChannel,
+) {
+    let mut name = name
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| format!("Track {}", project.tracks.len() + 1));
+
+    if show_channel {
+        name = format!("{} (Ch. {})", name, channel_number + 1);
+    }
+
+    let mut notes = channel.notes;
+    notes.sort_by(|a, b| a.offset.partial_cmp(&b.offset).unwrap());
+
+    for note in &mut notes {
+        note.id = project.new_note_id();
+    }
+
+    let control_lanes = channel
+        .control_lanes
+        .into_iter()
+        .map(|(controller, points)| ControlLane { controller, points })
+        .collect();
+
+    let id = project.new_track_id();
+
+    project.tracks.push(Track {
+        id,
+        name,
+        notes,
+        instrument: channel.instrument.unwrap_or(0),
+        channel: channel_number,
+        control_lanes,
+        pitch_bend: channel.pitch_bend,
+        bend_range: channel.bend_range.unwrap_or(DEFAULT_BEND_RANGE),
+    });
+}
+
+pub fn import_midi(name: &str, data: &[u8]) -> Result<Project, ImportError> {
+    let mut reader = Reader::new(data);
+
+    if reader
+        .read_bytes(4)
+        .map_err(|_| ImportError::NotAMidiFile)?
+        != b"MThd"
+    {
+        return Err(ImportError::NotAMidiFile);
+    }
+
+    let header_length = reader.read_u32()? as usize;
+
+    if header_length < 6 {
+        return Err(ImportError::NotAMidiFile);
+    }
+
+    let format = reader.read_u16()?;
+    let track_count = reader.read_u16()?;
+    let division = reader.read_u16()?;
+
+    reader.read_bytes(header_length - 6)?;
+
+    if format > 1 {
+        return Err(ImportError::UnsupportedFormat(format));
+    }
+
+    if division & 0x8000 != 0 || division == 0 {
+        return Err(ImportError::UnsupportedTimeDivision);
+    }
+
+    let ticks_per_whole_note = division as f64 * 4.0;
+
+    let mut project = Project::new(name);
+
+    let mut tempo_events = Vec::new();
+    let mut time_signatures = Vec::new();
+
+    let mut imported_tracks = Vec::new();
+
+    while !reader.is_empty() && imported_tracks.len() < track_count as usize {
+        let chunk_type = reader.read_bytes(4)?;
+        let chunk_length = reader.read_u32()? as usize;
+        let chunk = reader.read_bytes(chunk_length)?;
+
+        // Unknown chunk types must be skipped.
+        if chunk_type != b"MTrk" {
+            continue;
+        }
+
+        let mut track_reader = Reader::new(chunk);
+        let mut track = ImportedTrack::default();
+
+        let mut tick = 0u64;
+        let mut running_status = None;
+
+        while !track_reader.is_empty() {
+            tick += track_reader.read_varlen()? as u64;
+
+            let status = if track_reader.peek_u8()? & 0x80 != 0 {
+                track_reader.read_u8()?
+            } else {
+                running_status.ok_or(ImportError::MissingStatus)?
+            };
+
+            match status {
+                0xFF => {
+                    running_status = None;
+
+                    let meta_type = track_reader.read_u8()?;
+                    let length = track_reader.read_varlen()? as usize;
+                    let meta = track_reader.read_bytes(length)?;
+
+                    match meta_type {
+                        0x03 if track.name.is_none() => {
+                            track.name = Some(String::from_utf8_lossy(meta).trim().to_string());
+                        }
+                        0x2F => break,
+                        0x51 if meta.len() == 3 => {
+                            let tempo = u32::from_be_bytes([0, meta[0], meta[1], meta[2]]);
+
+                            if tempo > 0 {
+                                tempo_events.push(TempoEvent {
+                                    offset: tick as f64 / ticks_per_whole_note,
+                                    bpm: (60_000_000.0 / tempo as f64 * 100.0).round() / 100.0,
+                                    ramp: false,
+                                });
+                            }
+                        }
+                        0x58 if meta.len() >= 2 && meta[0] > 0 => {
+                            time_signatures.push((
+                                tick as f64 / ticks_per_whole_note,
+                                TimeSignature {
+                                    top: meta[0] as u32,
+                                    bottom: 1 << meta[1].min(6),
+                                },
+                            ));
+                        }
+                        _ => {}
+                    }
+                }
+                0xF0 | 0xF7 => {
+                    running_status = None;
+
+                    let length = track_reader.read_varlen()? as usize;
+                    track_reader.read_bytes(length)?;
+                }
+                0x80..=0xEF => {
+                    running_status = Some(status);
+
+                    let channel = track.channels.entry(status & 0x0F).or_default();
+                    let first = track_reader.read_u8()? & 0x7f;
+
+                    match status & 0xF0 {
+                        0xC0 => {
+                            channel.instrument.get_or_insert(first);
+                        }
+                        0xD0 => {}
+                        kind => {
+                            let second = track_reader.read_u8()? & 0x7f;
+
+                            match kind {
+                                0x90 if second > 0 => channel.note_on(tick, first, second),
+                                0x80 | 0x90 => channel.note_off(tick, first, ticks_per_whole_note),
+                                0xB0 => channel.control_change(
+                                    tick as f64 / ticks_per_whole_note,
+                                    first,
+                                    second,
+                                ),
+                                0xE0 => channel.pitch_bend(
+                                    tick,
+                                    ((second as i16) << 7 | first as i16) + MIN_BEND,
+                                    ticks_per_whole_note,
+                                ),
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+                _ => return Err(ImportError::InvalidStatus(status)),
+            }
+        }
+
+        for channel in track.channels.values_mut() {
+            channel.release_all(tick, ticks_per_whole_note);
+        }
+
+        imported_tracks.push(track);
+    }
+
+    tempo_events.sort_by(|a, b| a.offset.partial_cmp(&b.offset).unwrap());
+
+    for event in tempo_events {
+        let events = &mut project.tempo_map.events;
+        let last = events.last_mut().unwrap();
+
+        // Later events at the same offset override earlier ones.
+        if (last.offset - event.offset).abs() < 1e-9 {
+            *last = event;
+        } else {
+            events.push(event);
+        }
+    }
+
+    time_signatures.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    for (offset, time_signature) in time_signatures {
+        let map = &mut project.time_signature_map;
+
+        // Changes are only allowed on measure boundaries, so round misplaced ones up.
+        let mut measure = map.measure_at(offset);
+
+        if (map.measure_offset(measure) - offset).abs() > 1e-6 {
+            measure += 1;
+        }
+
+        let last = map.changes.last_mut().unwrap();
+
+        if last.measure == measure {
+            last.time_signature = time_signature;
+        } else if last.time_signature != time_signature {
+            map.changes.push(TimeSignatureChange {
+                measure,
+                time_signature,
+            });
+        }
+    }
+
+    // Channels without notes are often set up from a track of their own, so their instrument,
+    // controllers and bends go to the track playing notes on the channel, once it exists.
+    let mut setup_only = Vec::new();
+
+    for track in imported_tracks {
+        let multiple_channels = track
+            .channels
+            .values()
+            .filter(|channel| !channel.notes.is_empty())
+            .count()
+            > 1;
+
+        for (channel_number, channel) in track.channels {
+            if channel.notes.is_empty() {
+                if channel.has_setup() {
+                    setup_only.push((track.name.clone(), channel_number, channel));
+                }
+
+                continue;
+            }
+
+            add_track(
+                &mut project,
+                track.name.clone(),
+                channel_number,
+                multiple_channels,
+                channel,
+            );
+        }
+    }
+
+    for (track_name, channel_number, channel) in setup_only {
+        if let Some(track) = project
+            .tracks
+            .iter_mut()
+            .find(|track| track.channel == channel_number)
+        {
+            for (controller, mut points) in channel.control_lanes {
+                match track.control_lane_index(controller) {
+                    Some(index) => {
+                        let lane = &mut track.control_lanes[index];
+                        lane.points.append(&mut points);
+                        lane.points
+                            .sort_by(|a, b| a.offset.partial_cmp(&b.offset).unwrap());
+                    }
+                    None => track.control_lanes.push(ControlLane { controller, points }),
+                }
+            }
+
+            let mut pitch_bend = channel.pitch_bend;
+            track.pitch_bend.append(&mut pitch_bend);
+            track
+                .pitch_bend
+                .sort_by(|a, b| a.offset.partial_cmp(&b.offset).unwrap());
+
+            if let Some(bend_range) = channel.bend_range {
+                track.bend_range = bend_range;
+            }
+
+            if let Some(instrument) = channel.instrument {
+                track.instrument = instrument;
+            }
+
+            continue;
+        }
+
+        // No track plays notes on the channel, so its setup gets a track of its own.
+        add_track(&mut project, track_name, channel_number, true, channel);
+    }
+
+    Ok(project)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A file with the given MTrk chunks, at 96 ticks per quarter note.
+    fn smf(format: u16, tracks: &[&[u8]]) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&format.to_be_bytes());
+        header.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+        header.extend_from_slice(&96u16.to_be_bytes());
+
+        let mut bytes = Vec::new();
+        write_chunk(&mut bytes, b"MThd", &header);
+
+        for track in tracks {
+            write_chunk(&mut bytes, b"MTrk", track);
+        }
+
+        bytes
+    }
+
+    fn notes(track: &Track) -> Vec<(u8, u8, f64, f64)> {
+        track
+            .notes
+            .iter()
+            .map(|note| (note.pitch, note.velocity, note.offset, note.length))
+            .collect()
+    }
+
+    #[test]
+    fn running_status_and_velocity_zero_note_off() {
+        let track = [
+            0x00, 0x90, 60, 100, // note on
+            0x60, 64, 90, // running status note on, a quarter note later
+            0x00, 60, 0, // running status note off, by velocity zero
+            0x60, 0x80, 64, 0, // note off
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+
+        let project = import_midi("Song", &smf(0, &[&track])).unwrap();
+
+        assert_eq!(project.tracks.len(), 1);
+        assert_eq!(
+            notes(&project.tracks[0]),
+            [(60, 100, 0.0, 0.25), (64, 90, 0.25, 0.25)]
+        );
+    }
+
+    #[test]
+    fn skips_meta_and_sysex_events_by_length() {
+        let mut track = vec![0x00, 0xFF, 0x03, 4];
+        track.extend_from_slice(b"Lead");
+
+        // A text event longer than a single byte length can hold.
+        track.extend_from_slice(&[0x00, 0xFF, 0x01, 0x81, 0x48]);
+        track.extend_from_slice(&[0x90; 200]);
+
+        track.extend_from_slice(&[0x00, 0xF0, 3, 0x7E, 0x7F, 0xF7]);
+        track.extend_from_slice(&[0x00, 0x90, 60, 100, 0x60, 0x80, 60, 0]);
+        track.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+
+        let project = import_midi("Song", &smf(0, &[&track])).unwrap();
+
+        assert_eq!(project.tracks[0].name, "Lead");
+        assert_eq!(notes(&project.tracks[0]), [(60, 100, 0.0, 0.25)]);
+    }
+
+    #[test]
+    fn meta_events_cancel_running_status() {
+        let track = [
+            0x00, 0x90, 60, 100, 0x00, 0xFF, 0x01, 0x00, // empty text event
+            0x60, 60, 0,
+        ];
+
+        assert!(matches!(
+            import_midi("Song", &smf(0, &[&track])),
+            Err(ImportError::MissingStatus)
+        ));
+    }
+
+    #[test]
+    fn truncated_file() {
+        let track = [0x00, 0xFF, 0x03, 10, b'L'];
+
+        assert!(matches!(
+            import_midi("Song", &smf(0, &[&track])),
+            Err(ImportError::UnexpectedEnd)
+        ));
+    }
+
+    #[test]
+    fn format_1_conductor_track() {
+        let conductor = [
+            0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, // 120 BPM
+            0x00, 0xFF, 0x58, 0x04, 3, 2, 24, 8, // 3/4
+            0x82, 0x20, 0xFF, 0x51, 0x03, 0x0F, 0x42, 0x40, // 60 BPM after a measure
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+        let piano = [0x00, 0xC0, 5, 0x00, 0x90, 60, 100, 0x60, 0x80, 60, 0];
+        let bass = [0x00, 0x91, 36, 80, 0x82, 0x20, 0x81, 36, 0];
+
+        let project = import_midi("Song", &smf(1, &[&conductor, &piano, &bass])).unwrap();
+
+        let tempos: Vec<(f64, f64)> = project
+            .tempo_map
+            .events
+            .iter()
+            .map(|event| (event.offset, event.bpm))
+            .collect();
+        assert_eq!(tempos, [(0.0, 120.0), (0.75, 60.0)]);
+
+        let changes = &project.time_signature_map.changes;
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0].time_signature == TimeSignature { top: 3, bottom: 4 });
+
+        assert_eq!(project.tracks.len(), 2);
+        assert_eq!(project.tracks[0].instrument, 5);
+        assert_eq!(project.tracks[1].channel, 1);
+        assert_eq!(notes(&project.tracks[1]), [(36, 80, 0.0, 0.75)]);
+    }
+
+    #[test]
+    fn unsupported_format() {
+        assert!(matches!(
+            import_midi("Song", &smf(2, &[])),
+            Err(ImportError::UnsupportedFormat(2))
+        ));
+    }
+
+    #[test]
+    fn zero_length_note() {
+        let track = [0x00, 0x99, 36, 100, 0x00, 0x89, 36, 0];
+
+        let project = import_midi("Song", &smf(0, &[&track])).unwrap();
+
+        assert_eq!(notes(&project.tracks[0]), [(36, 100, 0.0, 1.0 / 384.0)]);
+    }
+
+    #[test]
+    fn export_and_import() {
+        let mut project = Project::new("Song");
+        project.tempo_map.events.push(TempoEvent {
+            offset: 1.0,
+            bpm: 90.0,
+            ramp: false,
+        });
+        project.time_signature_map.changes[0].time_signature = TimeSignature { top: 6, bottom: 8 };
+
+        let id = project.new_track_id();
+        let note_id = project.new_note_id();
+
+        project.tracks.push(Track {
+            id,
+            name: "Bass".to_string(),
+            notes: vec![Note {
+                id: note_id,
+                pitch: 40,
+                velocity: 70,
+                offset: 0.5,
+                length: 0.125,
+            }],
+            instrument: 33,
+            channel: 2,
+            control_lanes: vec![ControlLane {
+                controller: 7,
+                points: vec![ControlPoint {
+                    offset: 0.25,
+                    value: 90,
+                }],
+            }],
+            pitch_bend: Vec::new(),
+            bend_range: 12,
+        });
+
+        let imported = import_midi("Song", &export_midi(&project)).unwrap();
+
+        let tempos: Vec<(f64, f64)> = imported
+            .tempo_map
+            .events
+            .iter()
+            .map(|event| (event.offset, event.bpm))
+            .collect();
+        assert_eq!(tempos, [(0.0, 120.0), (1.0, 90.0)]);
+        assert!(
+            imported.time_signature_map.changes[0].time_signature
+                == TimeSignature { top: 6, bottom: 8 }
+        );
+
+        assert_eq!(imported.tracks.len(), 1);
+
+        let track = &imported.tracks[0];
+        assert_eq!(track.name, "Bass");
+        assert_eq!(track.instrument, 33);
+        assert_eq!(track.channel, 2);
+        assert_eq!(track.bend_range, 12);
+        assert_eq!(notes(track), [(40, 70, 0.5, 0.125)]);
+
+        assert_eq!(track.control_lanes.len(), 1);
+        assert_eq!(track.control_lanes[0].controller, 7);
+        assert!(
+            track.control_lanes[0].points
+                == [ControlPoint {
+                    offset: 0.25,
+                    value: 90
+                }]
+        );
+    }
 }
@@ -0,0 +1,156 @@
+use yew::events::KeyboardEvent;
+
+use crate::{playback::now, util::note_name, Model};
+
+/// Keys played as a piano keyboard, identified by their position rather than the character
+/// they type, with their distance in semitones from the lowest C.
+const NOTE_KEYS: [(&str, u8); 17] = [
+    ("KeyA", 0),
+    ("KeyW", 1),
+    ("KeyS", 2),
+    ("KeyE", 3),
+    ("KeyD", 4),
+    ("KeyF", 5),
+    ("KeyT", 6),
+    ("KeyG", 7),
+    ("KeyY", 8),
+    ("KeyH", 9),
+    ("KeyU", 10),
+    ("KeyJ", 11),
+    ("KeyK", 12),
+    ("KeyO", 13),
+    ("KeyL", 14),
+    ("KeyP", 15),
+    ("Semicolon", 16),
+];
+
+const OCTAVE_DOWN_KEY: &str = "KeyZ";
+const OCTAVE_UP_KEY: &str = "KeyX";
+const VELOCITY_DOWN_KEY: &str = "KeyC";
+const VELOCITY_UP_KEY: &str = "KeyV";
+
+const MAX_OCTAVE: u8 = 8;
+const VELOCITY_STEP: u8 = 20;
+
+/// Playing notes with the computer keyboard, as if it was a MIDI input device.
+pub struct MusicalTyping {
+    /// Octave of the lowest C, where C4 is middle C.
+    octave: u8,
+    velocity: u8,
+    /// Keys held down, with the pitches they started.
+    held_keys: Vec<(String, u8)>,
+}
+
+impl Default for MusicalTyping {
+    fn default() -> Self {
+        Self {
+            octave: 4,
+            velocity: 100,
+            held_keys: Vec::new(),
+        }
+    }
+}
+
+impl MusicalTyping {
+    /// Short description of the keyboard state for the top bar.
+    pub fn status(&self) -> String {
+        format!(
+            "{}–{}, velocity {}",
+            note_name(self.lowest_pitch()),
+            note_name((self.lowest_pitch() + 16).min(127)),
+            self.velocity
+        )
+    }
+
+    fn lowest_pitch(&self) -> u8 {
+        (self.octave + 1) * 12
+    }
+}
+
+impl Model {
+    pub fn toggle_musical_typing(&mut self) {
+        if self.musical_typing.is_some() {
+            self.release_typed_notes();
+            self.musical_typing = None;
+        } else {
+            self.musical_typing = Some(MusicalTyping::default());
+        }
+    }
+
+    /// Play a note, or change the octave or velocity, if the key is one of the musical keys.
+    /// Returns whether the key was used.
+    pub fn musical_typing_key_down(&mut self, event: &KeyboardEvent) -> bool {
+        let typing = match &mut self.musical_typing {
+            Some(typing) if !event.ctrl_key() && !event.meta_key() && !event.alt_key() => typing,
+            _ => return false,
+        };
+
+        let code = event.code();
+
+        match code.as_str() {
+            OCTAVE_DOWN_KEY => typing.octave = typing.octave.saturating_sub(1),
+            OCTAVE_UP_KEY => typing.octave = (typing.octave + 1).min(MAX_OCTAVE),
+            VELOCITY_DOWN_KEY => {
+                typing.velocity = typing.velocity.saturating_sub(VELOCITY_STEP).max(1)
+            }
+            VELOCITY_UP_KEY => typing.velocity = (typing.velocity + VELOCITY_STEP).min(127),
+            _ => {
+                let interval = match NOTE_KEYS.iter().find(|(key, _)| *key == code) {
+                    Some(&(_, interval)) => interval,
+                    None => return false,
+                };
+
+                // Keys repeat while held, but the note is already playing.
+                if event.repeat() || typing.held_keys.iter().any(|(key, _)| *key == code) {
+                    return true;
+                }
+
+                let pitch = typing.lowest_pitch() + interval;
+
+                if pitch > 127 {
+                    return true;
+                }
+
+                typing.held_keys.push((code, pitch));
+                let velocity = typing.velocity;
+
+                self.handle_midi_input(&[0x90, pitch, velocity], now());
+            }
+        }
+
+        true
+    }
+
+    /// Release the note started by the key, if any. Returns whether the key was used.
+    pub fn musical_typing_key_up(&mut self, event: &KeyboardEvent) -> bool {
+        let typing = match &mut self.musical_typing {
+            Some(typing) => typing,
+            None => return false,
+        };
+
+        let code = event.code();
+
+        let index = match typing.held_keys.iter().position(|(key, _)| *key == code) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        let (_, pitch) = typing.held_keys.remove(index);
+        self.handle_midi_input(&[0x80, pitch, 0], now());
+
+        true
+    }
+
+    /// Release all notes held on the computer keyboard, since key ups are missed once the
+    /// editor loses focus.
+    pub fn release_typed_notes(&mut self) {
+        let held_keys = match &mut self.musical_typing {
+            Some(typing) => std::mem::take(&mut typing.held_keys),
+            None => return,
+        };
+
+        for (_, pitch) in held_keys {
+            self.handle_midi_input(&[0x80, pitch, 0], now());
+        }
+    }
+}
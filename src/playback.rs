@@ -1,10 +1,54 @@
 use gloo_timers::callback::Interval;
-use js_sys::Array;
+use js_sys::{Array, Reflect};
 use wasm_bindgen::{JsCast, JsValue};
-use web_sys::MidiOutput;
+use web_sys::{MidiOutput, SvgLineElement};
 use yew::prelude::*;
 
-use crate::{project::MIN_INTERVAL, Model, Msg};
+use crate::{
+    automation::SUSTAIN_CONTROLLER,
+    midi::{sort_messages, MidiMessage, MidiMessageType},
+    Model, Msg,
+};
+
+/// How often the scheduler runs, in milliseconds.
+const SCHEDULER_INTERVAL: u32 = 25;
+
+/// How far ahead messages are scheduled, in milliseconds. Needs to cover the longest delay
+/// between two scheduler runs.
+const LOOKAHEAD: f64 = 100.0;
+
+/// State of running playback, which goes through the project in passes. The first pass starts
+/// at the playhead, and each one ends at the end of the loop region, if looping, or of the
+/// project.
+pub struct Playback {
+    /// Runs the scheduler until dropped.
+    _interval: Interval,
+    /// Messages of the current pass not scheduled yet.
+    queue: Vec<MidiMessage>,
+    /// Count-in clicks not scheduled yet, with their timestamps.
+    count_in: Vec<(f64, MidiMessageType)>,
+    start_offset: f64,
+    /// `performance.now()` timestamp of the start of the current pass.
+    start_time: f64,
+    /// Start offset and timestamp of the previous pass, which is still playing while the current
+    /// one is scheduled ahead of time.
+    previous_pass: Option<(f64, f64)>,
+    /// Where the current pass wraps around to the start of the loop region.
+    loop_end: Option<f64>,
+    /// End of the messages queued for the current pass. Without a loop, it moves on past the
+    /// end of the project while recording.
+    pass_end: f64,
+    /// Channels and pitches of the notes left sounding by the messages scheduled so far.
+    held_notes: Vec<(u8, u8)>,
+}
+
+/// Milliseconds on the clock used by MIDI timestamps.
+pub fn now() -> f64 {
+    web_sys::window()
+        .and_then(|window| window.performance())
+        .map(|performance| performance.now())
+        .unwrap_or(0.0)
+}
 
 impl Model {
     pub fn get_output_devices(&self) -> Vec<MidiOutput> {
@@ -39,48 +83,315 @@ impl Model {
     }
 
     pub fn play(&mut self, ctx: &Context<Self>) {
-        let output = match self.selected_output.clone() {
-            Some(output) => output,
-            None => return,
-        };
-
-        for pitch in 0..=127 {
-            self.stop_midi_note(pitch, None);
+        if self.selected_output.is_none() {
+            return;
         }
 
-        if self.tick_interval.is_some() {
-            self.tick_interval.take().unwrap().cancel();
+        if self.playback.is_some() {
+            self.stop_playback();
             ctx.link().send_message(Msg::SetPlayProgress(0.0));
             return;
         };
 
-        let whole_note_duration = 240.0 / self.project.bpm;
-        let min_interval_duration = whole_note_duration * MIN_INTERVAL;
-        let tick_interval = (min_interval_duration * 1000.0) as u32;
+        self.release_all_notes(None);
+
+        if let Some(output) = &self.selected_output {
+            for track in &self.project.tracks {
+                for message in track.bend_range_messages() {
+                    output.send(&message.to_array()).ok();
+                }
+            }
+        }
 
         let link = ctx.link().clone();
+        let interval = Interval::new(SCHEDULER_INTERVAL, move || {
+            link.send_message(Msg::PlaybackTick);
+        });
+
+        let loop_end = self.project.loop_region.end_after(self.play_offset);
+
+        // The project starts playing once the count-in is over.
+        let (mut count_in, count_in_duration) = self.count_in_messages(self.play_offset);
+        let start_time = now();
+
+        for (time, _) in &mut count_in {
+            *time += start_time;
+        }
+
+        self.playback = Some(Playback {
+            _interval: interval,
+            queue: self.pass_messages(self.play_offset, loop_end),
+            count_in,
+            start_offset: self.play_offset,
+            start_time: start_time + count_in_duration,
+            previous_pass: None,
+            loop_end,
+            pass_end: loop_end.unwrap_or_else(|| self.project.length()),
+            held_notes: Vec::new(),
+        });
+        self.play_progress = 0.0;
+
+        self.playback_tick();
+    }
+
+    pub fn stop_playback(&mut self) {
+        self.finish_recording();
+
+        if self.playback.take().is_some() {
+            // Drop the messages scheduled within the lookahead window. Some browsers don't
+            // implement `clear`, and calling it there would throw.
+            if let Some(output) = &self.selected_output {
+                if Reflect::has(output, &JsValue::from_str("clear")).unwrap_or(false) {
+                    output.clear();
+                }
+            }
+
+            // Without `clear`, notes may still start within the lookahead window.
+            self.release_all_notes(None);
+            self.release_all_notes(Some(now() + LOOKAHEAD));
+        }
+    }
+
+    /// Offset played at the given `performance.now()` timestamp, or the start of the first pass
+    /// during the count-in.
+    pub fn playback_offset_at(&self, time: f64) -> Option<f64> {
+        let playback = self.playback.as_ref()?;
+        let tempo_map = &self.project.tempo_map;
+
+        let (start_offset, start_time) = match playback.previous_pass {
+            Some(previous_pass) if time < playback.start_time => previous_pass,
+            _ => (playback.start_offset, playback.start_time),
+        };
+
+        let start_seconds = tempo_map.seconds_at(start_offset);
+        let elapsed = ((time - start_time) / 1000.0).max(0.0);
+
+        Some(tempo_map.offset_at(start_seconds + elapsed))
+    }
+
+    /// Messages to play from `start` up to the end of the loop, or of the project.
+    fn pass_messages(&self, start: f64, loop_end: Option<f64>) -> Vec<MidiMessage> {
+        let mut messages = self.project.to_midi();
+        let end = loop_end.unwrap_or_else(|| self.project.length());
+
+        messages.extend(self.metronome_messages(start, end));
+        sort_messages(&mut messages);
+
+        messages.retain(|message| {
+            message.offset >= start - 1e-4
+                && loop_end
+                    .map(|end| message.offset < end - 1e-4)
+                    .unwrap_or(true)
+        });
+
+        // Controllers and pitch bends start out with the values set before the pass, ahead of
+        // any points right at its start.
+        let mut pass = Vec::new();
+
+        for track in &self.project.tracks {
+            if let Some(value) = track.pitch_bend_at(start) {
+                pass.push(MidiMessage {
+                    offset: start,
+                    type_: MidiMessageType::PitchBend(track.channel, value),
+                });
+            }
+
+            for lane in &track.control_lanes {
+                if let Some(value) = lane.value_before(start) {
+                    pass.push(MidiMessage {
+                        offset: start,
+                        type_: MidiMessageType::ControlChange(
+                            track.channel,
+                            lane.controller,
+                            value,
+                        ),
+                    });
+                }
+            }
+        }
+
+        pass.append(&mut messages);
+        sort_messages(&mut pass);
+
+        pass
+    }
 
-        let mut midi = self.project.to_midi();
-        let mut local_offset = self.play_offset;
+    /// Schedule the messages due within the lookahead window and move the progress line.
+    /// Returns whether playback ended.
+    pub fn playback_tick(&mut self) -> bool {
+        let now = now();
 
-        self.tick_interval = Some(Interval::new(tick_interval, move || {
-            while !midi.is_empty() && (midi[0].offset - local_offset).abs() <= 1e-4 {
-                let array = midi.remove(0).type_.to_array();
-                output.send(&array).ok();
+        let mut playback = match self.playback.take() {
+            Some(playback) => playback,
+            None => return false,
+        };
+
+        let due = playback
+            .count_in
+            .iter()
+            .take_while(|(time, _)| *time <= now + LOOKAHEAD)
+            .count();
+
+        if let Some(output) = &self.selected_output {
+            for (time, type_) in &playback.count_in[..due] {
+                output.send_with_timestamp(&type_.to_array(), *time).ok();
+            }
+        }
+
+        playback.count_in.drain(..due);
+
+        let tempo_map = &self.project.tempo_map;
+
+        loop {
+            let start_seconds = tempo_map.seconds_at(playback.start_offset);
+
+            // Timestamps use the same clock as `performance.now()`.
+            let timestamp = |offset: f64| {
+                playback.start_time + (tempo_map.seconds_at(offset) - start_seconds) * 1000.0
+            };
+
+            // Recording goes on past the end of the project, with the metronome clicking along.
+            while self.recording.is_some()
+                && playback.loop_end.is_none()
+                && timestamp(playback.pass_end) <= now + LOOKAHEAD
+            {
+                let end = playback.pass_end + 1.0;
+
+                playback
+                    .queue
+                    .extend(self.metronome_messages(playback.pass_end, end));
+                sort_messages(&mut playback.queue);
+                playback.pass_end = end;
+            }
+
+            let due = playback
+                .queue
+                .iter()
+                .take_while(|message| timestamp(message.offset) <= now + LOOKAHEAD)
+                .count();
+
+            for message in &playback.queue[..due] {
+                match message.type_ {
+                    MidiMessageType::NoteOn(channel, pitch, _) => {
+                        playback.held_notes.push((channel, pitch));
+                    }
+                    MidiMessageType::NoteOff(channel, pitch, _) => {
+                        playback.held_notes.retain(|&note| note != (channel, pitch));
+                    }
+                    MidiMessageType::ChangeInstrument(..)
+                    | MidiMessageType::ControlChange(..)
+                    | MidiMessageType::PitchBend(..) => {}
+                }
+
+                if let Some(output) = &self.selected_output {
+                    output
+                        .send_with_timestamp(&message.type_.to_array(), timestamp(message.offset))
+                        .ok();
+                }
+            }
+
+            playback.queue.drain(..due);
+
+            // Wrap around once the whole pass is scheduled, cutting off notes that cross the
+            // end of the loop.
+            let end = match playback.loop_end {
+                Some(end) if playback.queue.is_empty() && timestamp(end) <= now + LOOKAHEAD => end,
+                _ => break,
+            };
+
+            let end_time = timestamp(end);
+
+            for (channel, pitch) in playback.held_notes.drain(..) {
+                self.stop_midi_note(channel, pitch, Some(end_time));
+            }
+
+            let start = self.project.loop_region.start;
+            let loop_end = self.project.loop_region.end_after(start);
+
+            playback.queue = self.pass_messages(start, loop_end);
+            playback.previous_pass = Some((playback.start_offset, playback.start_time));
+            playback.start_offset = start;
+            playback.start_time = end_time;
+            playback.loop_end = loop_end;
+            playback.pass_end = loop_end.unwrap_or_else(|| self.project.length());
+        }
+
+        // Right after wrapping around, the new pass hasn't started yet.
+        if now >= playback.start_time {
+            let start_seconds = tempo_map.seconds_at(playback.start_offset);
+            let elapsed = (now - playback.start_time) / 1000.0;
+            let offset = tempo_map.offset_at(start_seconds + elapsed);
+
+            if playback.loop_end.is_none()
+                && self.recording.is_none()
+                && playback.queue.is_empty()
+                && offset >= self.project.length()
+            {
+                self.playback = Some(playback);
+                self.stop_playback();
+                self.play_progress = 0.0;
+                return true;
+            }
+
+            self.play_progress = offset - self.play_offset;
+        }
+
+        self.playback = Some(playback);
+        self.update_progress_line();
+
+        false
+    }
+
+    pub fn update_progress_line(&self) {
+        if let Some(progress_line) = self.progress_line.cast::<SvgLineElement>() {
+            let x = (self.play_offset + self.play_progress) * self.zoom.whole_note_width();
+
+            for animated_length in [progress_line.x1(), progress_line.x2()] {
+                animated_length.base_val().set_value(x as f32).ok();
             }
+        }
+    }
+
+    /// Send note offs for every pitch on the channels in use, release the sustain pedal and
+    /// center the pitch bend, now or at the given timestamp.
+    fn release_all_notes(&self, timestamp: Option<f64>) {
+        let mut channels: Vec<u8> = self
+            .project
+            .tracks
+            .iter()
+            .map(|track| track.channel)
+            .chain([self.metronome.channel])
+            .collect();
+        channels.sort_unstable();
+        channels.dedup();
+
+        for channel in channels {
+            for pitch in 0..=127 {
+                self.stop_midi_note(channel, pitch, timestamp);
+            }
+
+            if let Some(output) = &self.selected_output {
+                let timestamp = timestamp.unwrap_or_else(now);
 
-            link.send_message(Msg::IncrementPlayProgress);
-            local_offset += MIN_INTERVAL;
-        }));
+                for message in [
+                    MidiMessageType::ControlChange(channel, SUSTAIN_CONTROLLER, 0),
+                    MidiMessageType::PitchBend(channel, 0),
+                ] {
+                    output
+                        .send_with_timestamp(&message.to_array(), timestamp)
+                        .ok();
+                }
+            }
+        }
     }
 
-    pub fn play_midi_note(&self, instrument: u8, pitch: u8, duration: f64) {
+    pub fn play_midi_note(&self, channel: u8, instrument: u8, pitch: u8, duration: f64) {
         let output = match self.selected_output.as_ref() {
             Some(output) => output,
             None => return,
         };
 
-        let opcode = JsValue::from_f64(0xC0 as _);
+        let opcode = JsValue::from_f64((0xC0 | channel) as _);
         let instrument = JsValue::from_f64(instrument as _);
 
         let message = Array::of2(&opcode, &instrument);
@@ -90,17 +401,18 @@ impl Model {
         let full_velocity = JsValue::from_f64(0x7f as _);
 
         let message = Array::of3(
-            &JsValue::from_f64(0x90 as _),
+            &JsValue::from_f64((0x90 | channel) as _),
             &JsValue::from_f64(pitch as _),
             &full_velocity,
         );
 
         output.send(&message).ok();
 
-        self.stop_midi_note(pitch, Some(duration));
+        self.stop_midi_note(channel, pitch, Some(now() + duration));
     }
 
-    pub fn stop_midi_note(&self, pitch: u8, timeout: Option<f64>) {
+    /// Send a note off, now or at the given `performance.now()` timestamp.
+    pub fn stop_midi_note(&self, channel: u8, pitch: u8, timestamp: Option<f64>) {
         let output = match self.selected_output.as_ref() {
             Some(output) => output,
             None => return,
@@ -109,10 +421,14 @@ impl Model {
         let pitch = JsValue::from_f64(pitch as _);
         let full_velocity = JsValue::from_f64(0x7f as _);
 
-        let message = Array::of3(&JsValue::from_f64(0x80 as _), &pitch, &full_velocity);
+        let message = Array::of3(
+            &JsValue::from_f64((0x80 | channel) as _),
+            &pitch,
+            &full_velocity,
+        );
 
-        if let Some(timeout) = timeout {
-            output.send_with_timestamp(&message, timeout).ok();
+        if let Some(timestamp) = timestamp {
+            output.send_with_timestamp(&message, timestamp).ok();
         } else {
             output.send(&message).ok();
         }
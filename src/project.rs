@@ -1,27 +1,128 @@
+use serde::{Deserialize, Serialize};
+
+use crate::util::snap;
+
 pub const WHOLE_NOTE_WIDTH: f64 = 320.0;
 pub const NOTE_RECT_HEIGHT: f64 = 30.0;
 pub const NOTE_EDGE_WIDTH: f64 = 6.0;
+pub const VELOCITY_BAR_WIDTH: f64 = 6.0;
+pub const TEMPO_POINT_RADIUS: f64 = 5.0;
+pub const CONTROL_POINT_RADIUS: f64 = 4.0;
+
+/// Tempo range that can be entered.
+pub const MIN_BPM: f64 = 1.0;
+pub const MAX_BPM: f64 = 5000.0;
+
+/// Tempo range shown by the tempo lane.
+pub const MIN_LANE_BPM: f64 = 20.0;
+pub const MAX_LANE_BPM: f64 = 300.0;
 
+/// Zoom factors are limited to this range.
+pub const MIN_ZOOM: f64 = 0.125;
+pub const MAX_ZOOM: f64 = 8.0;
+
+pub const DRUM_CHANNEL: u8 = 9;
+
+/// Pitch bend range, in semitones either way, set by synthesizers unless told otherwise.
+pub const DEFAULT_BEND_RANGE: u8 = 2;
+pub const MAX_BEND_RANGE: u8 = 48;
+/// Pitch bend values are 14 bit, centered at zero.
+pub const MIN_BEND: i16 = -8192;
+pub const MAX_BEND: i16 = 8191;
+/// Bends drawn this close to zero snap to it.
+pub const BEND_CENTER_SNAP: i16 = 256;
+
+/// Divisions of a whole note in the default grid.
 pub const MIN_DIVISION: u32 = 16;
+/// Shortest length of a note, and least distance between loop markers or tempo events. Also
+/// the step of automation drawn without a grid.
 pub const MIN_INTERVAL: f64 = 1.0 / MIN_DIVISION as f64;
 
-#[derive(Clone)]
+/// Identifies a track independently of its position in `Project::tracks`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct TrackId(pub u64);
+
+/// Identifies a note independently of its position in `Track::notes`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct NoteId(pub u64);
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Project {
     pub name: String,
-    pub time_signature: TimeSignature,
-    pub bpm: f64,
+    pub time_signature_map: TimeSignatureMap,
+    pub tempo_map: TempoMap,
     pub tracks: Vec<Track>,
+    /// Next unused track or note ID.
+    pub next_id: u64,
+    /// Grid for placing notes, which doesn't affect playback.
+    #[serde(default)]
+    pub grid: Grid,
+    #[serde(default)]
+    pub loop_region: LoopRegion,
 }
 
 impl Project {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            time_signature_map: TimeSignatureMap::new(TimeSignature { top: 4, bottom: 4 }),
+            tempo_map: TempoMap::new(120.0),
+            tracks: Vec::new(),
+            next_id: 0,
+            grid: Grid::default(),
+            loop_region: LoopRegion::default(),
+        }
+    }
+
+    fn allocate_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    pub fn new_track_id(&mut self) -> TrackId {
+        TrackId(self.allocate_id())
+    }
+
+    pub fn new_note_id(&mut self) -> NoteId {
+        NoteId(self.allocate_id())
+    }
+
+    pub fn track_index(&self, id: TrackId) -> Option<usize> {
+        self.tracks.iter().position(|track| track.id == id)
+    }
+
+    pub fn track_mut(&mut self, id: TrackId) -> &mut Track {
+        self.tracks
+            .iter_mut()
+            .find(|track| track.id == id)
+            .expect("no track with this ID")
+    }
+
+    /// The first channel not used by any track, skipping the GM drum channel.
+    pub fn free_channel(&self) -> u8 {
+        (0..16)
+            .filter(|channel| *channel != DRUM_CHANNEL)
+            .find(|channel| self.tracks.iter().all(|track| track.channel != *channel))
+            .unwrap_or(0)
+    }
+
+    /// End of the last note, or of the last automation point.
     pub fn length(&self) -> f64 {
         self.tracks
             .iter()
             .map(|track| {
-                track
-                    .notes
+                let notes_end = track.notes.iter().map(|note| note.offset + note.length);
+                let lanes_end = track
+                    .control_lanes
                     .iter()
-                    .map(|note| note.offset + note.length)
+                    .filter_map(|lane| lane.points.last())
+                    .map(|point| point.offset);
+                let bend_end = track.pitch_bend.last().map(|point| point.offset);
+
+                notes_end
+                    .chain(lanes_end)
+                    .chain(bend_end)
                     .reduce(f64::max)
                     .unwrap_or(0.0)
             })
@@ -30,7 +131,252 @@ impl Project {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TempoEvent {
+    /// Offset in whole notes.
+    pub offset: f64,
+    pub bpm: f64,
+    /// Ramp linearly towards the next event's tempo instead of jumping to it.
+    pub ramp: bool,
+}
+
+/// Tempo events sorted by offset. The first event is always at offset zero.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TempoMap {
+    pub events: Vec<TempoEvent>,
+}
+
+impl TempoEvent {
+    pub fn screen_x(&self, zoom: Zoom) -> f64 {
+        self.offset * zoom.whole_note_width()
+    }
+
+    pub fn screen_y(&self, lane_height: f64) -> f64 {
+        let progress = (self.bpm - MIN_LANE_BPM) / (MAX_LANE_BPM - MIN_LANE_BPM);
+        (1.0 - progress.clamp(0.0, 1.0)) * lane_height
+    }
+}
+
+impl TempoMap {
+    pub fn new(bpm: f64) -> Self {
+        Self {
+            events: vec![TempoEvent {
+                offset: 0.0,
+                bpm,
+                ramp: false,
+            }],
+        }
+    }
+
+    pub fn initial_bpm(&self) -> f64 {
+        self.events[0].bpm
+    }
+
+    /// Index of the event in effect at `offset`.
+    pub fn event_index_at(&self, offset: f64) -> usize {
+        self.events
+            .iter()
+            .rposition(|event| event.offset <= offset + 1e-9)
+            .unwrap_or(0)
+    }
+
+    pub fn bpm_at(&self, offset: f64) -> f64 {
+        self.bpm_within(self.event_index_at(offset), offset)
+    }
+
+    // Tempo at `offset`, assuming it lies within the segment starting at event `index`.
+    fn bpm_within(&self, index: usize, offset: f64) -> f64 {
+        let event = &self.events[index];
+
+        match self.events.get(index + 1) {
+            Some(next) if event.ramp => {
+                let progress = (offset - event.offset) / (next.offset - event.offset);
+                event.bpm + (next.bpm - event.bpm) * progress.clamp(0.0, 1.0)
+            }
+            _ => event.bpm,
+        }
+    }
+
+    /// Time in seconds from the beginning of the project to `offset`.
+    pub fn seconds_at(&self, offset: f64) -> f64 {
+        let mut seconds = 0.0;
+
+        for (index, event) in self.events.iter().enumerate() {
+            if event.offset >= offset {
+                break;
+            }
+
+            let end = self
+                .events
+                .get(index + 1)
+                .map(|next| next.offset.min(offset))
+                .unwrap_or(offset);
+
+            let length = end - event.offset;
+
+            let start_bpm = event.bpm;
+            let end_bpm = self.bpm_within(index, end);
+
+            // A whole note lasts 240 / bpm seconds; integrate that over a linear tempo ramp.
+            seconds += if (end_bpm - start_bpm).abs() < 1e-6 {
+                240.0 * length / start_bpm
+            } else {
+                240.0 * length * (end_bpm / start_bpm).ln() / (end_bpm - start_bpm)
+            };
+        }
+
+        seconds
+    }
+
+    pub fn seconds_between(&self, start: f64, end: f64) -> f64 {
+        self.seconds_at(end) - self.seconds_at(start)
+    }
+
+    /// Offset reached `seconds` after the beginning of the project. The inverse of
+    /// `seconds_at`.
+    pub fn offset_at(&self, seconds: f64) -> f64 {
+        let mut remaining = seconds;
+
+        for (index, event) in self.events.iter().enumerate() {
+            let start_bpm = event.bpm;
+
+            let next = match self.events.get(index + 1) {
+                Some(next) => next,
+                None => return event.offset + remaining * start_bpm / 240.0,
+            };
+
+            let duration = self.seconds_between(event.offset, next.offset);
+
+            if remaining < duration {
+                let slope = (self.bpm_within(index, next.offset) - start_bpm)
+                    / (next.offset - event.offset);
+
+                // Solve the integral from `seconds_at` for the length.
+                return event.offset
+                    + if slope.abs() < 1e-9 {
+                        remaining * start_bpm / 240.0
+                    } else {
+                        start_bpm * ((slope * remaining / 240.0).exp() - 1.0) / slope
+                    };
+            }
+
+            remaining -= duration;
+        }
+
+        unreachable!("the tempo map is never empty")
+    }
+
+    pub fn get_event_at_position(
+        &self,
+        x: f64,
+        y: f64,
+        lane_height: f64,
+        zoom: Zoom,
+    ) -> Option<usize> {
+        let mut result = None;
+
+        for (index, event) in self.events.iter().enumerate() {
+            let dx = x - event.screen_x(zoom);
+            let dy = y - event.screen_y(lane_height);
+
+            if dx.hypot(dy) <= TEMPO_POINT_RADIUS * 1.5 {
+                result = Some(index);
+            }
+        }
+
+        result
+    }
+}
+
+/// Part of the project that playback repeats, if enabled.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LoopRegion {
+    pub enabled: bool,
+    pub start: f64,
+    pub end: f64,
+}
+
+impl Default for LoopRegion {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            start: 0.0,
+            end: 1.0,
+        }
+    }
+}
+
+impl LoopRegion {
+    /// Where playback from `offset` wraps around, unless it doesn't loop.
+    pub fn end_after(&self, offset: f64) -> Option<f64> {
+        let looping = self.enabled && self.end - self.start > 1e-4 && offset < self.end - 1e-4;
+        looping.then_some(self.end)
+    }
+}
+
+/// Snapping grid. Divisions are note values, like 16 for sixteenth notes.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Grid {
+    Off,
+    Straight(u32),
+    Dotted(u32),
+    Triplet(u32),
+    Quintuplet(u32),
+}
+
+impl Default for Grid {
+    fn default() -> Self {
+        Grid::Straight(MIN_DIVISION)
+    }
+}
+
+impl Grid {
+    /// Every grid the user can choose from.
+    pub fn all() -> Vec<Grid> {
+        let mut grids = vec![Grid::Off];
+
+        for division in [4, 8, 16, 32, 64, 128] {
+            grids.push(Grid::Straight(division));
+            grids.push(Grid::Dotted(division));
+            grids.push(Grid::Triplet(division));
+            grids.push(Grid::Quintuplet(division));
+        }
+
+        grids
+    }
+
+    /// Distance between grid lines in whole notes, or `None` for free placement.
+    pub fn interval(&self) -> Option<f64> {
+        match *self {
+            Grid::Off => None,
+            Grid::Straight(division) => Some(1.0 / division as f64),
+            Grid::Dotted(division) => Some(1.5 / division as f64),
+            // Three notes in the time of two, and five in the time of four.
+            Grid::Triplet(division) => Some(2.0 / 3.0 / division as f64),
+            Grid::Quintuplet(division) => Some(4.0 / 5.0 / division as f64),
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            Grid::Off => "Off".to_string(),
+            Grid::Straight(division) => format!("1/{}", division),
+            Grid::Dotted(division) => format!("1/{} dotted", division),
+            Grid::Triplet(division) => format!("1/{} triplet", division),
+            Grid::Quintuplet(division) => format!("1/{} quintuplet", division),
+        }
+    }
+
+    /// Snap an offset to the nearest grid line.
+    pub fn snap(&self, offset: f64) -> f64 {
+        match self.interval() {
+            Some(interval) => snap(offset, interval),
+            None => offset,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct TimeSignature {
     pub top: u32,
     pub bottom: u32,
@@ -41,29 +387,234 @@ impl TimeSignature {
         self.top as f64 / self.bottom as f64
     }
 
-    pub fn measure_width(&self) -> f64 {
-        self.measure_length() * WHOLE_NOTE_WIDTH
+    pub fn measure_width(&self, zoom: Zoom) -> f64 {
+        self.measure_length() * zoom.whole_note_width()
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TimeSignatureChange {
+    /// Zero-based index of the first measure using this time signature.
+    pub measure: u32,
+    pub time_signature: TimeSignature,
+}
+
+/// Time signature changes sorted by measure. The first change is always at measure zero.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TimeSignatureMap {
+    pub changes: Vec<TimeSignatureChange>,
+}
+
+/// A measure of the project, as laid out by the time signature map.
+pub struct Measure<'a> {
+    /// Zero-based measure index.
+    pub index: u32,
+    /// Offset in whole notes.
+    pub offset: f64,
+    pub time_signature: &'a TimeSignature,
+}
+
+impl TimeSignatureMap {
+    pub fn new(time_signature: TimeSignature) -> Self {
+        Self {
+            changes: vec![TimeSignatureChange {
+                measure: 0,
+                time_signature,
+            }],
+        }
+    }
+
+    pub fn initial(&self) -> &TimeSignature {
+        &self.changes[0].time_signature
+    }
+
+    /// All measures starting before `end`.
+    pub fn measures(&self, end: f64) -> Vec<Measure<'_>> {
+        let mut measures = Vec::new();
+
+        let mut offset = 0.0;
+
+        for (index, change) in self.changes.iter().enumerate() {
+            let last_measure = self
+                .changes
+                .get(index + 1)
+                .map(|next| next.measure)
+                .unwrap_or(u32::MAX);
+
+            let mut measure = change.measure;
+
+            while measure < last_measure {
+                if offset >= end - 1e-9 {
+                    return measures;
+                }
+
+                measures.push(Measure {
+                    index: measure,
+                    offset,
+                    time_signature: &change.time_signature,
+                });
+
+                offset += change.time_signature.measure_length();
+                measure += 1;
+            }
+        }
+
+        measures
+    }
+
+    /// Offset of the beginning of `measure` in whole notes.
+    pub fn measure_offset(&self, measure: u32) -> f64 {
+        let mut offset = 0.0;
+
+        for (index, change) in self.changes.iter().enumerate() {
+            let segment_end = self
+                .changes
+                .get(index + 1)
+                .map(|next| next.measure)
+                .unwrap_or(u32::MAX)
+                .min(measure);
+
+            if segment_end <= change.measure {
+                break;
+            }
+
+            offset +=
+                (segment_end - change.measure) as f64 * change.time_signature.measure_length();
+        }
+
+        offset
+    }
+
+    /// Index of the measure containing `offset`.
+    pub fn measure_at(&self, offset: f64) -> u32 {
+        let mut start = 0.0;
+
+        for (index, change) in self.changes.iter().enumerate() {
+            let measure_length = change.time_signature.measure_length();
+            let elapsed = ((offset - start) / measure_length + 1e-9).floor().max(0.0) as u32;
+
+            match self.changes.get(index + 1) {
+                Some(next) if change.measure + elapsed >= next.measure => {
+                    start += (next.measure - change.measure) as f64 * measure_length;
+                }
+                _ => return change.measure + elapsed,
+            }
+        }
+
+        0
+    }
+
+    pub fn time_signature_at(&self, offset: f64) -> &TimeSignature {
+        let measure = self.measure_at(offset);
+
+        self.changes
+            .iter()
+            .rev()
+            .find(|change| change.measure <= measure)
+            .map(|change| &change.time_signature)
+            .unwrap_or(self.initial())
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Track {
+    pub id: TrackId,
     pub name: String,
     pub notes: Vec<Note>,
     pub instrument: u8,
+    /// Zero-based MIDI channel.
+    pub channel: u8,
+    /// Automation of MIDI controllers, one lane per controller.
+    #[serde(default)]
+    pub control_lanes: Vec<ControlLane>,
+    /// Pitch bend curve, interpolated linearly between the points. Sorted by offset.
+    #[serde(default)]
+    pub pitch_bend: Vec<BendPoint>,
+    /// Range of the pitch bend curve, in semitones either way.
+    #[serde(default = "default_bend_range")]
+    pub bend_range: u8,
+}
+
+fn default_bend_range() -> u8 {
+    DEFAULT_BEND_RANGE
 }
 
 impl Track {
-    pub fn get_note_at_position(&self, x: f64, y: f64) -> Option<usize> {
+    pub fn note_index(&self, id: NoteId) -> Option<usize> {
+        self.notes.iter().position(|note| note.id == id)
+    }
+
+    pub fn note_mut(&mut self, id: NoteId) -> &mut Note {
+        self.notes
+            .iter_mut()
+            .find(|note| note.id == id)
+            .expect("no note with this ID")
+    }
+
+    /// The pitch bend at `offset`, if the curve has started by then.
+    pub fn pitch_bend_at(&self, offset: f64) -> Option<i16> {
+        let index = self
+            .pitch_bend
+            .iter()
+            .rposition(|point| point.offset <= offset + 1e-4)?;
+        let point = self.pitch_bend[index];
+
+        match self.pitch_bend.get(index + 1) {
+            Some(next) if next.offset > point.offset => {
+                let progress = (offset - point.offset) / (next.offset - point.offset);
+                let value = point.value as f64 + (next.value - point.value) as f64 * progress;
+
+                Some(value.round() as i16)
+            }
+            _ => Some(point.value),
+        }
+    }
+
+    pub fn control_lane_index(&self, controller: u8) -> Option<usize> {
+        self.control_lanes
+            .iter()
+            .position(|lane| lane.controller == controller)
+    }
+
+    pub fn control_lane_mut(&mut self, controller: u8) -> &mut ControlLane {
+        self.control_lanes
+            .iter_mut()
+            .find(|lane| lane.controller == controller)
+            .expect("no lane for this controller")
+    }
+
+    /// Notes overlapping the rectangle between two corners, in piano roll coordinates.
+    pub fn get_notes_in_rect(
+        &self,
+        (x1, y1): (f64, f64),
+        (x2, y2): (f64, f64),
+        zoom: Zoom,
+    ) -> Vec<NoteId> {
+        let (left, right) = (x1.min(x2), x1.max(x2));
+        let (top, bottom) = (y1.min(y2), y1.max(y2));
+
+        self.notes
+            .iter()
+            .filter(|note| {
+                note.screen_x(zoom) < right
+                    && note.right_edge(zoom) > left
+                    && note.screen_y(zoom) < bottom
+                    && note.bottom_edge(zoom) > top
+            })
+            .map(|note| note.id)
+            .collect()
+    }
+
+    pub fn get_note_at_position(&self, x: f64, y: f64, zoom: Zoom) -> Option<usize> {
         let mut result = None;
 
         for (index, note) in self.notes.iter().enumerate() {
             let epsilon = 1e-3;
 
-            let note_x = note.screen_x();
-            let note_y = note.screen_y();
-            let note_w = note.screen_width();
-            let note_h = note.screen_height();
+            let note_x = note.screen_x(zoom);
+            let note_y = note.screen_y(zoom);
+            let note_w = note.screen_width(zoom);
+            let note_h = note.screen_height(zoom);
 
             let x = x - note_x;
             let y = y - note_y;
@@ -75,10 +626,82 @@ impl Track {
 
         result
     }
+
+    /// Find the note whose bar in the velocity lane is under `x`. Bars are centered on the
+    /// start of their note, and can be grabbed up to a bar width away on either side.
+    pub fn get_note_at_velocity_bar(&self, x: f64, zoom: Zoom) -> Option<usize> {
+        let mut result = None;
+
+        for (index, note) in self.notes.iter().enumerate() {
+            let x = x - note.screen_x(zoom);
+
+            if x.abs() <= VELOCITY_BAR_WIDTH {
+                result = Some(index);
+            }
+        }
+
+        result
+    }
+}
+
+/// Control change events of a single controller. Values hold until the next point.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ControlLane {
+    pub controller: u8,
+    /// Sorted by offset.
+    pub points: Vec<ControlPoint>,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ControlPoint {
+    /// Offset in whole notes.
+    pub offset: f64,
+    pub value: u8,
+}
+
+impl ControlLane {
+    /// The value in effect right before `offset`.
+    pub fn value_before(&self, offset: f64) -> Option<u8> {
+        self.points
+            .iter()
+            .take_while(|point| point.offset < offset - 1e-4)
+            .last()
+            .map(|point| point.value)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BendPoint {
+    /// Offset in whole notes.
+    pub offset: f64,
+    /// From `MIN_BEND` to `MAX_BEND`.
+    pub value: i16,
+}
+
+impl BendPoint {
+    pub fn screen_x(&self, zoom: Zoom) -> f64 {
+        self.offset * zoom.whole_note_width()
+    }
+
+    pub fn screen_y(&self, lane_height: f64) -> f64 {
+        let progress = (self.value - MIN_BEND) as f64 / (MAX_BEND - MIN_BEND) as f64;
+        (1.0 - progress) * lane_height
+    }
+}
+
+impl ControlPoint {
+    pub fn screen_x(&self, zoom: Zoom) -> f64 {
+        self.offset * zoom.whole_note_width()
+    }
+
+    pub fn screen_y(&self, lane_height: f64) -> f64 {
+        (1.0 - self.value as f64 / 127.0) * lane_height
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Note {
+    pub id: NoteId,
     pub pitch: u8,
     pub velocity: u8,
     /// Offset in whole notes.
@@ -88,27 +711,125 @@ pub struct Note {
 }
 
 impl Note {
-    pub fn screen_x(&self) -> f64 {
-        self.offset * WHOLE_NOTE_WIDTH
+    pub fn screen_x(&self, zoom: Zoom) -> f64 {
+        self.offset * zoom.whole_note_width()
+    }
+
+    pub fn screen_y(&self, zoom: Zoom) -> f64 {
+        (127 - self.pitch) as f64 * zoom.note_height()
+    }
+
+    pub fn screen_width(&self, zoom: Zoom) -> f64 {
+        self.length * zoom.whole_note_width()
+    }
+
+    pub fn screen_height(&self, zoom: Zoom) -> f64 {
+        zoom.note_height()
+    }
+
+    pub fn right_edge(&self, zoom: Zoom) -> f64 {
+        self.screen_x(zoom) + self.screen_width(zoom)
     }
 
-    pub fn screen_y(&self) -> f64 {
-        (127 - self.pitch) as f64 * NOTE_RECT_HEIGHT
+    pub fn bottom_edge(&self, zoom: Zoom) -> f64 {
+        self.screen_y(zoom) + self.screen_height(zoom)
     }
+}
+
+/// Scale of the piano roll, relative to `WHOLE_NOTE_WIDTH` and `NOTE_RECT_HEIGHT`.
+#[derive(Clone, Copy)]
+pub struct Zoom {
+    pub horizontal: f64,
+    pub vertical: f64,
+}
 
-    pub fn screen_width(&self) -> f64 {
-        self.length * WHOLE_NOTE_WIDTH
+impl Default for Zoom {
+    fn default() -> Self {
+        Self {
+            horizontal: 1.0,
+            vertical: 1.0,
+        }
     }
+}
 
-    pub fn screen_height(&self) -> f64 {
-        NOTE_RECT_HEIGHT
+impl Zoom {
+    pub fn whole_note_width(&self) -> f64 {
+        WHOLE_NOTE_WIDTH * self.horizontal
     }
 
-    pub fn right_edge(&self) -> f64 {
-        self.screen_x() + self.screen_width()
+    pub fn note_height(&self) -> f64 {
+        NOTE_RECT_HEIGHT * self.vertical
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    pub fn bottom_edge(&self) -> f64 {
-        self.screen_y() + self.screen_height()
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    /// 60 BPM ramping up to 120 BPM over the first whole note, then 120 BPM until 90 BPM at
+    /// offset 3.
+    fn ramp_map() -> TempoMap {
+        TempoMap {
+            events: vec![
+                TempoEvent {
+                    offset: 0.0,
+                    bpm: 60.0,
+                    ramp: true,
+                },
+                TempoEvent {
+                    offset: 1.0,
+                    bpm: 120.0,
+                    ramp: false,
+                },
+                TempoEvent {
+                    offset: 3.0,
+                    bpm: 90.0,
+                    ramp: false,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn constant_tempo() {
+        let map = TempoMap::new(120.0);
+
+        assert_close(map.seconds_at(0.0), 0.0);
+        assert_close(map.seconds_at(1.0), 2.0);
+        assert_close(map.offset_at(3.0), 1.5);
+    }
+
+    #[test]
+    fn tempo_ramp() {
+        let map = ramp_map();
+
+        assert_close(map.bpm_at(0.5), 90.0);
+        assert_close(map.bpm_at(2.0), 120.0);
+
+        // A whole note lasts 240 / bpm seconds, integrated over the ramp.
+        let ramp_seconds = 4.0 * 2f64.ln();
+
+        assert_close(map.seconds_at(1.0), ramp_seconds);
+        assert_close(map.seconds_at(3.0), ramp_seconds + 4.0);
+        assert_close(map.seconds_at(4.0), ramp_seconds + 4.0 + 240.0 / 90.0);
+        assert_close(map.offset_at(ramp_seconds), 1.0);
+    }
+
+    #[test]
+    fn offset_at_inverts_seconds_at() {
+        let map = ramp_map();
+
+        for offset in [0.0, 0.1, 0.5, 0.99, 1.0, 2.5, 3.0, 7.25] {
+            assert_close(map.offset_at(map.seconds_at(offset)), offset);
+        }
     }
 }
@@ -0,0 +1,250 @@
+use std::{collections::HashSet, fmt};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::project::{Grid, Project, MAX_BEND, MAX_BEND_RANGE, MIN_BEND};
+
+/// Version written into saved projects. Bump it whenever the format changes in a way that
+/// `#[serde(default)]` can't cover, and add a step to `migrate`.
+pub const PROJECT_FILE_VERSION: u32 = 2;
+
+pub const PROJECT_FILE_EXTENSION: &str = "json";
+
+#[derive(Serialize)]
+struct ProjectFileRef<'a> {
+    version: u32,
+    project: &'a Project,
+}
+
+#[derive(Deserialize)]
+struct ProjectFile {
+    version: u32,
+    project: Value,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Json(serde_json::Error),
+    UnsupportedVersion(u32),
+    Invalid(&'static str),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Json(error) => write!(f, "malformed project file: {}", error),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "project file version {} is newer than this editor supports",
+                version
+            ),
+            Self::Invalid(reason) => write!(f, "invalid project: {}", reason),
+        }
+    }
+}
+
+impl From<serde_json::Error> for LoadError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::Json(error)
+    }
+}
+
+pub fn save_project(project: &Project) -> String {
+    let file = ProjectFileRef {
+        version: PROJECT_FILE_VERSION,
+        project,
+    };
+
+    serde_json::to_string(&file).expect("serialize project")
+}
+
+pub fn load_project(data: &str) -> Result<Project, LoadError> {
+    let file: ProjectFile = serde_json::from_str(data)?;
+
+    if file.version > PROJECT_FILE_VERSION {
+        return Err(LoadError::UnsupportedVersion(file.version));
+    }
+
+    let mut value = file.project;
+
+    for version in file.version..PROJECT_FILE_VERSION {
+        value = migrate(version, value)?;
+    }
+
+    let project: Project = serde_json::from_value(value)?;
+
+    validate(&project)?;
+
+    Ok(project)
+}
+
+/// Upgrade a project from `version` to `version + 1`.
+fn migrate(version: u32, mut value: Value) -> Result<Value, LoadError> {
+    match version {
+        // Version 2 gives every track and note a stable id.
+        1 => {
+            let project = value
+                .as_object_mut()
+                .ok_or(LoadError::Invalid("the project must be an object"))?;
+            let mut next_id = 0;
+
+            if let Some(tracks) = project.get_mut("tracks").and_then(Value::as_array_mut) {
+                for track in tracks.iter_mut().filter_map(Value::as_object_mut) {
+                    track.insert("id".into(), next_id.into());
+                    next_id += 1;
+
+                    if let Some(notes) = track.get_mut("notes").and_then(Value::as_array_mut) {
+                        for note in notes.iter_mut().filter_map(Value::as_object_mut) {
+                            note.insert("id".into(), next_id.into());
+                            next_id += 1;
+                        }
+                    }
+                }
+            }
+
+            project.insert("next_id".into(), next_id.into());
+
+            Ok(value)
+        }
+        _ => Err(LoadError::UnsupportedVersion(version)),
+    }
+}
+
+/// Check the invariants the editor relies on. Runs after migrating, so older files are checked
+/// the same way.
+fn validate(project: &Project) -> Result<(), LoadError> {
+    match project.tempo_map.events.first() {
+        Some(event) if event.offset == 0.0 => {}
+        _ => return Err(LoadError::Invalid("the tempo map must start at offset 0")),
+    }
+
+    if project
+        .tempo_map
+        .events
+        .iter()
+        .any(|event| !event.bpm.is_finite() || event.bpm <= 0.0)
+    {
+        return Err(LoadError::Invalid("tempos must be positive"));
+    }
+
+    if project
+        .tempo_map
+        .events
+        .windows(2)
+        .any(|pair| !is_offset(pair[1].offset) || pair[1].offset <= pair[0].offset)
+    {
+        return Err(LoadError::Invalid(
+            "tempo events must be sorted, with distinct offsets",
+        ));
+    }
+
+    match project.time_signature_map.changes.first() {
+        Some(change) if change.measure == 0 => {}
+        _ => {
+            return Err(LoadError::Invalid(
+                "the time signature map must start at measure 1",
+            ))
+        }
+    }
+
+    if project
+        .time_signature_map
+        .changes
+        .windows(2)
+        .any(|pair| pair[1].measure <= pair[0].measure)
+    {
+        return Err(LoadError::Invalid(
+            "time signature changes must be sorted, with distinct measures",
+        ));
+    }
+
+    if project.time_signature_map.changes.iter().any(|change| {
+        let time_signature = &change.time_signature;
+        time_signature.top == 0 || !time_signature.bottom.is_power_of_two()
+    }) {
+        return Err(LoadError::Invalid("malformed time signature"));
+    }
+
+    match project.grid {
+        Grid::Straight(0) | Grid::Dotted(0) | Grid::Triplet(0) | Grid::Quintuplet(0) => {
+            return Err(LoadError::Invalid("malformed grid"))
+        }
+        _ => {}
+    }
+
+    let loop_region = &project.loop_region;
+
+    if !is_offset(loop_region.start)
+        || !loop_region.end.is_finite()
+        || loop_region.end <= loop_region.start
+    {
+        return Err(LoadError::Invalid("malformed loop region"));
+    }
+
+    if project.tracks.iter().any(|track| {
+        track.channel > 15
+            || track.instrument > 127
+            || track.notes.iter().any(|note| {
+                note.pitch > 127
+                    || note.velocity > 127
+                    || !is_offset(note.offset)
+                    || !note.length.is_finite()
+                    || note.length <= 0.0
+            })
+            || track.control_lanes.iter().any(|lane| {
+                lane.controller > 127
+                    || lane
+                        .points
+                        .iter()
+                        .any(|point| point.value > 127 || !is_offset(point.offset))
+                    || lane
+                        .points
+                        .windows(2)
+                        .any(|pair| pair[1].offset < pair[0].offset)
+            })
+            || track.bend_range > MAX_BEND_RANGE
+            || track.pitch_bend.iter().any(|point| {
+                !(MIN_BEND..=MAX_BEND).contains(&point.value) || !is_offset(point.offset)
+            })
+            || track
+                .pitch_bend
+                .windows(2)
+                .any(|pair| pair[1].offset < pair[0].offset)
+    }) {
+        return Err(LoadError::Invalid("track data out of range"));
+    }
+
+    // Actions find tracks and notes by id, so ids must be unique, and new ones must not collide
+    // with those in use.
+    let mut track_ids = HashSet::new();
+    let mut note_ids = HashSet::new();
+
+    for track in &project.tracks {
+        if !track_ids.insert(track.id) {
+            return Err(LoadError::Invalid("duplicate track id"));
+        }
+
+        for note in &track.notes {
+            if !note_ids.insert(note.id) {
+                return Err(LoadError::Invalid("duplicate note id"));
+            }
+        }
+    }
+
+    if track_ids
+        .iter()
+        .map(|id| id.0)
+        .chain(note_ids.iter().map(|id| id.0))
+        .any(|id| id >= project.next_id)
+    {
+        return Err(LoadError::Invalid("ids must be lower than next_id"));
+    }
+
+    Ok(())
+}
+
+/// Whether `value` is a usable offset: finite and not before the start of the project.
+fn is_offset(value: f64) -> bool {
+    value.is_finite() && value >= 0.0
+}
@@ -0,0 +1,109 @@
+use crate::{action::Action, project::Grid, Model};
+
+pub struct QuantizeSettings {
+    /// Any grid but `Grid::Off`.
+    pub grid: Grid,
+    /// How far notes are moved towards the grid, from 0 to 1.
+    pub strength: f64,
+    /// Delay of every other grid line, from 0 to 1 of half a grid step.
+    pub swing: f64,
+    /// Quantize note ends as well, instead of keeping note lengths.
+    pub quantize_ends: bool,
+}
+
+impl Default for QuantizeSettings {
+    fn default() -> Self {
+        Self {
+            grid: Grid::default(),
+            strength: 1.0,
+            swing: 0.0,
+            quantize_ends: false,
+        }
+    }
+}
+
+impl QuantizeSettings {
+    fn interval(&self) -> f64 {
+        self.grid.interval().expect("quantize grid can't be off")
+    }
+
+    fn grid_line(&self, index: i64) -> f64 {
+        let interval = self.interval();
+        let swing = if index % 2 == 0 {
+            0.0
+        } else {
+            self.swing * interval / 2.0
+        };
+
+        index as f64 * interval + swing
+    }
+
+    /// Move an offset towards the nearest grid line.
+    pub fn quantize(&self, offset: f64) -> f64 {
+        let interval = self.interval();
+        let index = (offset / interval).round() as i64;
+
+        // Swing can make a neighbouring line the nearest one.
+        let nearest = (index - 1..=index + 1)
+            .map(|index| self.grid_line(index))
+            .min_by(|a, b| (a - offset).abs().total_cmp(&(b - offset).abs()))
+            .unwrap();
+
+        (offset + (nearest - offset) * self.strength).max(0.0)
+    }
+}
+
+impl Model {
+    /// Quantize the selected notes, or the whole track if none are selected, as a single undo
+    /// step.
+    pub fn quantize_notes(&mut self) {
+        let track = match self.selected_track_index {
+            None => return,
+            Some(index) => &self.project.tracks[index],
+        };
+
+        let settings = &self.quantize_settings;
+        let whole_track = !track
+            .notes
+            .iter()
+            .any(|note| self.selected_notes.contains(&note.id));
+
+        let edits: Vec<Action> = track
+            .notes
+            .iter()
+            .filter(|note| whole_track || self.selected_notes.contains(&note.id))
+            .filter_map(|note| {
+                let offset = settings.quantize(note.offset);
+
+                let length = if settings.quantize_ends {
+                    let end = settings.quantize(note.offset + note.length);
+
+                    // Notes shorter than a grid step would vanish otherwise.
+                    if end - offset > 1e-4 {
+                        end - offset
+                    } else {
+                        note.length
+                    }
+                } else {
+                    note.length
+                };
+
+                if offset == note.offset && length == note.length {
+                    None
+                } else {
+                    Some(Action::EditNote(
+                        track.id, note.id, offset, note.pitch, length,
+                    ))
+                }
+            })
+            .collect();
+
+        self.begin_transaction();
+
+        for edit in edits {
+            self.perform_action(edit);
+        }
+
+        self.commit_transaction();
+    }
+}
@@ -0,0 +1,257 @@
+use js_sys::Array;
+use wasm_bindgen::JsCast;
+use web_sys::MidiInput;
+use yew::prelude::*;
+
+use crate::{
+    action::Action,
+    midi::MidiMessageType,
+    playback::now,
+    project::{Note, TrackId},
+    Model,
+};
+
+/// Notes played on the input device while the project plays, added to a track once recording
+/// stops.
+pub struct Recording {
+    pub track_id: TrackId,
+    /// Pitches being held, with the offset and velocity they were pressed at.
+    held_notes: Vec<(u8, f64, u8)>,
+    pub notes: Vec<RecordedNote>,
+}
+
+pub struct RecordedNote {
+    pub pitch: u8,
+    pub velocity: u8,
+    pub offset: f64,
+    pub length: f64,
+}
+
+/// Channel voice messages from an input device, with the channel ignored.
+fn parse_input(data: &[u8]) -> Option<MidiMessageType> {
+    match *data {
+        // A note on with velocity 0 is a note off.
+        [status, pitch, velocity] if status & 0xf0 == 0x90 && velocity > 0 => {
+            Some(MidiMessageType::NoteOn(0, pitch, velocity))
+        }
+        [status, pitch, velocity] if status & 0xf0 == 0x80 || status & 0xf0 == 0x90 => {
+            Some(MidiMessageType::NoteOff(0, pitch, velocity))
+        }
+        _ => None,
+    }
+}
+
+impl Model {
+    pub fn get_input_devices(&self) -> Vec<MidiInput> {
+        let mut input_devices = Vec::new();
+
+        let midi_access = match &self.midi_access {
+            Some(midi_access) => midi_access,
+            None => return input_devices,
+        };
+
+        let iter = js_sys::try_iter(midi_access.inputs().as_ref())
+            .expect("try_iter(midi_access)")
+            .expect("midi_access (get_input_devices)");
+
+        for entry in iter {
+            match entry {
+                Err(_) => break,
+                Ok(entry) => {
+                    let array: Array = entry.dyn_into().expect("dyn_into (array)");
+
+                    let input = array
+                        .get(1)
+                        .dyn_into::<MidiInput>()
+                        .expect("dyn_into::<MidiInput>");
+
+                    input_devices.push(input);
+                }
+            }
+        }
+
+        input_devices
+    }
+
+    /// Listen to the given input device instead of the current one.
+    pub fn set_input_device(&mut self, input: Option<MidiInput>) {
+        if let Some(old_input) = &self.selected_input {
+            old_input.set_onmidimessage(None);
+        }
+
+        if let Some(input) = &input {
+            input.set_onmidimessage(Some(self.input_closure.as_ref().unchecked_ref()));
+        }
+
+        self.selected_input = input;
+    }
+
+    /// Start recording into the selected track, counting in and starting playback if needed,
+    /// or stop recording along with playback.
+    pub fn toggle_recording(&mut self, ctx: &Context<Self>) {
+        if self.recording.is_some() {
+            self.stop_playback();
+            return;
+        }
+
+        let track_id = match self.selected_track_id() {
+            Some(id) => id,
+            None => return,
+        };
+
+        self.recording = Some(Recording {
+            track_id,
+            held_notes: Vec::new(),
+            notes: Vec::new(),
+        });
+
+        if self.playback.is_none() {
+            self.play(ctx);
+        }
+
+        // Playback couldn't start without an output.
+        if self.playback.is_none() {
+            self.recording = None;
+        }
+    }
+
+    /// Handle a message from the input device, received at the given timestamp. Returns
+    /// whether a note was recorded or entered.
+    pub fn handle_midi_input(&mut self, data: &[u8], time: f64) -> bool {
+        let message = match parse_input(data) {
+            Some(message) => message,
+            None => return false,
+        };
+
+        self.play_through(&message);
+
+        if self.step_input.is_some() {
+            return match message {
+                MidiMessageType::NoteOn(_, pitch, velocity) => {
+                    self.step_input_key_down(pitch, velocity);
+                    false
+                }
+                MidiMessageType::NoteOff(_, pitch, _) => self.step_input_key_up(pitch),
+                _ => false,
+            };
+        }
+
+        let offset = match self.playback_offset_at(time) {
+            Some(offset) => offset,
+            None => return false,
+        };
+        let loop_end = self.project.loop_region.end;
+
+        let recording = match &mut self.recording {
+            Some(recording) => recording,
+            None => return false,
+        };
+
+        match message {
+            MidiMessageType::NoteOn(_, pitch, velocity) => {
+                recording.release(pitch, offset, loop_end);
+                recording.held_notes.push((pitch, offset, velocity));
+                false
+            }
+            MidiMessageType::NoteOff(_, pitch, _) => recording.release(pitch, offset, loop_end),
+            _ => false,
+        }
+    }
+
+    /// Echo notes played on the input device to the selected track's instrument.
+    fn play_through(&self, message: &MidiMessageType) {
+        let (output, track) = match (&self.selected_output, self.selected_track_index) {
+            (Some(output), Some(index)) => (output, &self.project.tracks[index]),
+            _ => return,
+        };
+
+        let message = match *message {
+            MidiMessageType::NoteOn(_, pitch, velocity) => {
+                let instrument = MidiMessageType::ChangeInstrument(track.channel, track.instrument);
+                output.send(&instrument.to_array()).ok();
+
+                MidiMessageType::NoteOn(track.channel, pitch, velocity)
+            }
+            MidiMessageType::NoteOff(_, pitch, velocity) => {
+                MidiMessageType::NoteOff(track.channel, pitch, velocity)
+            }
+            _ => return,
+        };
+
+        output.send(&message.to_array()).ok();
+    }
+
+    /// Add the recorded notes to their track as a single undo step. Notes still held end at
+    /// the current playback position.
+    pub fn finish_recording(&mut self) {
+        let mut recording = match self.recording.take() {
+            Some(recording) => recording,
+            None => return,
+        };
+
+        if let Some(offset) = self.playback_offset_at(now()) {
+            let loop_end = self.project.loop_region.end;
+
+            while let Some(&(pitch, _, _)) = recording.held_notes.first() {
+                recording.release(pitch, offset, loop_end);
+            }
+        }
+
+        // The track may have been deleted while recording.
+        let track_index = match self.project.track_index(recording.track_id) {
+            Some(index) => index,
+            None => return,
+        };
+
+        let first_index = self.project.tracks[track_index].notes.len();
+        let mut creations = Vec::new();
+
+        for (index, note) in (first_index..).zip(&recording.notes) {
+            creations.push(Action::CreateNote(
+                recording.track_id,
+                index,
+                Note {
+                    id: self.project.new_note_id(),
+                    pitch: note.pitch,
+                    velocity: note.velocity,
+                    offset: note.offset,
+                    length: note.length,
+                },
+            ));
+        }
+
+        self.begin_transaction();
+
+        for creation in creations {
+            self.perform_action(creation);
+        }
+
+        self.commit_transaction();
+    }
+}
+
+impl Recording {
+    /// End the note held at the given pitch, if any. Returns whether a note was recorded.
+    fn release(&mut self, pitch: u8, offset: f64, loop_end: f64) -> bool {
+        let index = match self.held_notes.iter().position(|note| note.0 == pitch) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        let (_, start, velocity) = self.held_notes.remove(index);
+
+        // Playback wrapped around since the note started, so cut it at the end of the loop.
+        let end = if offset < start { loop_end } else { offset };
+
+        if end - start > 1e-4 {
+            self.notes.push(RecordedNote {
+                pitch,
+                velocity,
+                offset: start,
+                length: end - start,
+            });
+        }
+
+        true
+    }
+}
@@ -0,0 +1,155 @@
+use crate::{
+    action::Action,
+    project::{Note, NoteId, TrackId},
+    Model,
+};
+
+/// Entering notes one step at a time at the playhead, which advances by the length of the last
+/// placed note after every step.
+#[derive(Default)]
+pub struct StepInput {
+    /// Keys held down for the chord being entered.
+    held_keys: Vec<u8>,
+    /// Pitches and velocities of the chord being entered, added once all keys are released.
+    chord: Vec<(u8, u8)>,
+    /// Steps entered so far, last one last.
+    steps: Vec<Step>,
+}
+
+struct Step {
+    /// Where the playhead was before the step.
+    offset: f64,
+    track_id: TrackId,
+    /// Notes created by the step, none for a rest.
+    notes: Vec<NoteId>,
+}
+
+impl Model {
+    pub fn toggle_step_input(&mut self) {
+        if self.step_input.take().is_none() {
+            self.stop_playback();
+            self.play_progress = 0.0;
+            self.step_input = Some(StepInput::default());
+        }
+    }
+
+    /// Collect a key pressed on the input device into the chord being entered.
+    pub fn step_input_key_down(&mut self, pitch: u8, velocity: u8) {
+        if self.playback.is_some() {
+            return;
+        }
+
+        if let Some(step_input) = &mut self.step_input {
+            step_input.held_keys.retain(|&key| key != pitch);
+            step_input.held_keys.push(pitch);
+
+            if !step_input.chord.iter().any(|&(other, _)| other == pitch) {
+                step_input.chord.push((pitch, velocity));
+            }
+        }
+    }
+
+    /// Enter the chord once all its keys are released. Returns whether a step was entered.
+    pub fn step_input_key_up(&mut self, pitch: u8) -> bool {
+        let step_input = match &mut self.step_input {
+            Some(step_input) => step_input,
+            None => return false,
+        };
+
+        step_input.held_keys.retain(|&key| key != pitch);
+
+        if !step_input.held_keys.is_empty() || step_input.chord.is_empty() {
+            return false;
+        }
+
+        let chord = std::mem::take(&mut step_input.chord);
+
+        let track_index = match self.selected_track_index {
+            Some(index) => index,
+            None => return false,
+        };
+
+        let track_id = self.project.tracks[track_index].id;
+        let first_index = self.project.tracks[track_index].notes.len();
+        let mut creations = Vec::new();
+        let mut notes = Vec::new();
+
+        for (index, (pitch, velocity)) in (first_index..).zip(chord) {
+            let id = self.project.new_note_id();
+
+            creations.push(Action::CreateNote(
+                track_id,
+                index,
+                Note {
+                    id,
+                    pitch,
+                    velocity,
+                    offset: self.play_offset,
+                    length: self.last_placed_note_length,
+                },
+            ));
+
+            notes.push(id);
+        }
+
+        self.begin_transaction();
+
+        for creation in creations {
+            self.perform_action(creation);
+        }
+
+        self.commit_transaction();
+
+        self.advance_step(track_id, notes);
+        true
+    }
+
+    /// Leave a rest of the length of the last placed note.
+    pub fn step_input_rest(&mut self) {
+        if let Some(track_id) = self.selected_track_id() {
+            self.advance_step(track_id, Vec::new());
+        }
+    }
+
+    /// Delete the notes of the last step and move the playhead back to where it was.
+    pub fn step_input_back(&mut self) {
+        let step = match self.step_input.as_mut().and_then(|input| input.steps.pop()) {
+            Some(step) => step,
+            None => return,
+        };
+
+        if let Some(track_index) = self.project.track_index(step.track_id) {
+            let track = &self.project.tracks[track_index];
+
+            // Notes may have been deleted, or undone, since.
+            let deletions: Vec<Action> = step
+                .notes
+                .into_iter()
+                .filter(|&id| track.note_index(id).is_some())
+                .map(|id| Action::DeleteNote(track.id, id))
+                .collect();
+
+            self.begin_transaction();
+
+            for deletion in deletions {
+                self.perform_action(deletion);
+            }
+
+            self.commit_transaction();
+        }
+
+        self.play_offset = step.offset;
+    }
+
+    fn advance_step(&mut self, track_id: TrackId, notes: Vec<NoteId>) {
+        if let Some(step_input) = &mut self.step_input {
+            step_input.steps.push(Step {
+                offset: self.play_offset,
+                track_id,
+                notes,
+            });
+
+            self.play_offset += self.last_placed_note_length;
+        }
+    }
+}
@@ -1,15 +1,19 @@
-use wasm_bindgen::JsCast;
-use web_sys::{Element, HtmlSelectElement};
+use js_sys::{Array, Uint8Array};
+use wasm_bindgen::{prelude::Closure, JsCast};
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{Blob, Element, File, HtmlAnchorElement, HtmlInputElement, HtmlSelectElement, Url};
 use yew::prelude::*;
 
-use crate::project::{MIN_INTERVAL, NOTE_RECT_HEIGHT, WHOLE_NOTE_WIDTH};
+use crate::project::{
+    Grid, Zoom, BEND_CENTER_SNAP, DRUM_CHANNEL, MAX_BEND, MAX_LANE_BPM, MIN_BEND, MIN_LANE_BPM,
+};
 
-pub fn time_signature_options(values: &[u32]) -> Vec<Html> {
+pub fn time_signature_options(values: &[u32], selected: u32) -> Vec<Html> {
     values
         .iter()
         .map(|x| {
             html! {
-                <option value={ x.to_string() } selected={ x == &4 }>
+                <option value={ x.to_string() } selected={ *x == selected }>
                     { x.to_string() }
                 </option>
             }
@@ -17,11 +21,36 @@ pub fn time_signature_options(values: &[u32]) -> Vec<Html> {
         .collect()
 }
 
+/// Options for the 16 MIDI channels, numbered from one.
+pub fn channel_options(selected: u8) -> Vec<Html> {
+    (0..16u8)
+        .map(|channel| {
+            let label = if channel == DRUM_CHANNEL {
+                format!("{} (Drums)", channel + 1)
+            } else {
+                (channel + 1).to_string()
+            };
+
+            html! {
+                <option value={ channel.to_string() } selected={ channel == selected }>
+                    { label }
+                </option>
+            }
+        })
+        .collect()
+}
+
 pub fn select_get_value(event: Event) -> Option<String> {
     let target = event.target();
     let select = target.and_then(|target| target.dyn_into::<HtmlSelectElement>().ok());
 
-    select.and_then(|select| Some(select.value()))
+    select.map(|select| select.value())
+}
+
+/// Whether the event was sent to a form field, which handles keys and clipboard events itself.
+pub fn is_text_field(event: &Event) -> bool {
+    event.target_dyn_into::<HtmlInputElement>().is_some()
+        || event.target_dyn_into::<HtmlSelectElement>().is_some()
 }
 
 pub fn note_name(midi_note: u8) -> String {
@@ -60,11 +89,123 @@ pub fn relative_mouse_pos(event: &MouseEvent) -> (f64, f64) {
         .unwrap_or((0.0, 0.0))
 }
 
-pub fn mouse_x_to_interval(mouse_x: f64) -> f64 {
-    snap(mouse_x / WHOLE_NOTE_WIDTH, MIN_INTERVAL)
+pub fn mouse_x_to_interval(mouse_x: f64, grid: Grid, zoom: Zoom) -> f64 {
+    grid.snap(mouse_x / zoom.whole_note_width())
+}
+
+pub fn mouse_y_to_velocity(mouse_y: f64, lane_height: f64) -> u8 {
+    let velocity = (1.0 - mouse_y / lane_height) * 127.0;
+    velocity.round().clamp(1.0, 127.0) as u8
 }
 
-pub fn mouse_y_to_pitch(mouse_y: f64) -> u8 {
-    let pitch = 127.0 - mouse_y / NOTE_RECT_HEIGHT;
+pub fn mouse_y_to_control_value(mouse_y: f64, lane_height: f64) -> u8 {
+    let value = (1.0 - mouse_y / lane_height) * 127.0;
+    value.round().clamp(0.0, 127.0) as u8
+}
+
+pub fn mouse_y_to_bend(mouse_y: f64, lane_height: f64) -> i16 {
+    let progress = (1.0 - mouse_y / lane_height).clamp(0.0, 1.0);
+    let bend = (MIN_BEND as f64 + (MAX_BEND - MIN_BEND) as f64 * progress).round() as i16;
+
+    // Make it easy to return to no bend at all.
+    if bend.abs() < BEND_CENTER_SNAP {
+        0
+    } else {
+        bend
+    }
+}
+
+pub fn mouse_y_to_bpm(mouse_y: f64, lane_height: f64) -> f64 {
+    let progress = (1.0 - mouse_y / lane_height).clamp(0.0, 1.0);
+    (MIN_LANE_BPM + (MAX_LANE_BPM - MIN_LANE_BPM) * progress).round()
+}
+
+pub fn mouse_y_to_pitch(mouse_y: f64, zoom: Zoom) -> u8 {
+    let pitch = 127.0 - mouse_y / zoom.note_height();
     pitch.clamp(0.0, 127.0).ceil() as u8
 }
+
+pub fn download_file(data: &[u8], file_name: &str) {
+    let document = match web_sys::window().and_then(|window| window.document()) {
+        Some(document) => document,
+        None => return,
+    };
+
+    let anchor = match document
+        .create_element("a")
+        .ok()
+        .and_then(|anchor| anchor.dyn_into::<HtmlAnchorElement>().ok())
+    {
+        Some(anchor) => anchor,
+        None => return,
+    };
+
+    let array = Uint8Array::new_with_length(data.len() as u32);
+
+    for (i, byte) in data.iter().enumerate() {
+        array.set_index(i as u32, *byte);
+    }
+
+    let href = match Blob::new_with_u8_array_sequence(&Array::of1(&array))
+        .and_then(|blob| Url::create_object_url_with_blob(&blob))
+    {
+        Ok(href) => href,
+        Err(_) => return,
+    };
+
+    if let Some(body) = document.body() {
+        body.append_child(&anchor).unwrap();
+
+        anchor.set_href(&href);
+        anchor.set_download(file_name);
+        anchor.click();
+
+        body.remove_child(&anchor).unwrap();
+    }
+
+    Url::revoke_object_url(&href).ok();
+}
+
+/// Show a file picker and pass the chosen file's name and contents to `callback`.
+pub fn open_file_dialog(accept: &str, callback: Callback<(String, Vec<u8>)>) {
+    let input = match web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.create_element("input").ok())
+        .and_then(|input| input.dyn_into::<HtmlInputElement>().ok())
+    {
+        Some(input) => input,
+        None => return,
+    };
+
+    input.set_type("file");
+    input.set_accept(accept);
+
+    let input_copy = input.clone();
+
+    let onchange = Closure::once_into_js(move || {
+        let file = match input_copy.files().and_then(|files| files.get(0)) {
+            Some(file) => file,
+            None => return,
+        };
+
+        spawn_local(async move {
+            if let Some(data) = read_file(&file).await {
+                callback.emit((file.name(), data));
+            }
+        });
+    });
+
+    input.set_onchange(Some(onchange.unchecked_ref()));
+    input.click();
+}
+
+async fn read_file(file: &File) -> Option<Vec<u8>> {
+    let buffer = JsFuture::from(file.array_buffer()).await.ok()?;
+    Some(Uint8Array::new(&buffer).to_vec())
+}
+
+pub fn alert(message: &str) {
+    if let Some(window) = web_sys::window() {
+        window.alert_with_message(message).ok();
+    }
+}
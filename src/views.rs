@@ -1,17 +1,38 @@
 use wasm_bindgen::JsCast;
-use web_sys::{HtmlInputElement, HtmlSelectElement};
+use web_sys::{HtmlInputElement, HtmlSelectElement, Node};
 use yew::{
-    events::{Event, InputEvent},
+    events::{Event, InputEvent, KeyboardEvent, WheelEvent},
     prelude::*,
 };
 
 use crate::{
-    project::{MIN_INTERVAL, NOTE_RECT_HEIGHT, WHOLE_NOTE_WIDTH},
-    util::{note_name, select_get_value, time_signature_options},
-    Model, Msg,
+    automation::{controller_label, controller_name, is_automatable, AutomationLane},
+    metronome::MAX_COUNT_IN,
+    project::{
+        ControlLane, Grid, Track, CONTROL_POINT_RADIUS, MAX_BEND_RANGE, MAX_BPM, MIN_BEND, MIN_BPM,
+        TEMPO_POINT_RADIUS, VELOCITY_BAR_WIDTH,
+    },
+    util::{channel_options, note_name, select_get_value, time_signature_options},
+    Model, MouseOperation, Msg,
 };
 
 pub const PIANO_KEYS_WIDTH: f64 = 50.0;
+pub const VELOCITY_LANE_HEIGHT: f64 = 80.0;
+pub const TEMPO_LANE_HEIGHT: f64 = 60.0;
+pub const CONTROL_LANE_HEIGHT: f64 = 60.0;
+pub const BEND_LANE_HEIGHT: f64 = 60.0;
+/// Distance in pixels from which loop markers can be dragged.
+pub const LOOP_MARKER_GRAB_WIDTH: f64 = 6.0;
+/// Factor applied by one step of the zoom buttons or the mouse wheel.
+pub const ZOOM_STEP: f64 = 1.25;
+
+/// An editing lane stacked under the piano roll.
+pub struct Lane {
+    label: String,
+    height: f64,
+    content: Html,
+    onmousedown: Callback<MouseEvent>,
+}
 
 impl Model {
     pub fn view_no_midi(&self) -> Html {
@@ -21,8 +42,34 @@ impl Model {
     }
 
     pub fn view_main(&self, ctx: &Context<Self>) -> Html {
+        let onkeydown = ctx
+            .link()
+            .callback(|event: KeyboardEvent| Msg::KeyDown(event));
+        let onkeyup = ctx.link().callback(Msg::KeyUp);
+
+        // Focus moving to another element of the view still leaves the keyboard to it. Events are
+        // delegated, so the current target isn't the view itself.
+        let onfocusout = ctx.link().batch_callback(|event: FocusEvent| {
+            let main_view = web_sys::window()
+                .and_then(|window| window.document())
+                .and_then(|document| document.get_element_by_id("main-view"));
+            let focused = event
+                .related_target()
+                .and_then(|target| target.dyn_into::<Node>().ok());
+
+            match (main_view, focused) {
+                (Some(main_view), Some(focused)) if main_view.contains(Some(&focused)) => None,
+                _ => Some(Msg::MainViewBlur),
+            }
+        });
+
+        let oncopy = ctx.link().callback(Msg::Copy);
+        let oncut = ctx.link().callback(Msg::Cut);
+        let onpaste = ctx.link().callback(Msg::Paste);
+
         html! {
-            <div id="main-view">
+            <div id="main-view" tabindex="-1" { onkeydown } { onkeyup } { onfocusout } { oncopy }
+                 { oncut } { onpaste }>
                 { self.view_top_bar(ctx) }
                 { self.view_project_panel(ctx) }
                 { self.view_piano_roll(ctx) }
@@ -33,22 +80,77 @@ impl Model {
     pub fn view_top_bar(&self, ctx: &Context<Self>) -> Html {
         html! {
             <div id="top-bar" class="h-box frame dark">
+                { self.view_restore_prompt(ctx) }
                 { self.view_controls(ctx) }
                 { self.view_bpm(ctx) }
                 { self.view_time_signature(ctx) }
+                { self.view_grid(ctx) }
+                { self.view_zoom(ctx) }
                 { self.view_output_selection(ctx) }
+                { self.view_input_selection(ctx) }
+            </div>
+        }
+    }
+
+    pub fn view_restore_prompt(&self, ctx: &Context<Self>) -> Html {
+        let session = match &self.recovered_session {
+            Some(session) => session,
+            None => return html! {},
+        };
+
+        let restore = ctx.link().callback(|_| Msg::RestoreSession);
+        let discard = ctx.link().callback(|_| Msg::DiscardSession);
+
+        html! {
+            <div class="v-box frame">
+                <span>{ format!("Restore \"{}\" from the last session?", session.project.name) }</span>
+                <div class="h-box">
+                    <button onclick={ restore }>{ "Restore" }</button>
+                    <button onclick={ discard }>{ "Discard" }</button>
+                </div>
             </div>
         }
     }
 
     pub fn view_controls(&self, ctx: &Context<Self>) -> Html {
         let toggle = ctx.link().callback(|_| Msg::TogglePlayback);
+        let toggle_loop = ctx.link().callback(|_| Msg::ToggleLoop);
+        let loop_class = classes!(self.project.loop_region.enabled.then_some("active"));
+        let toggle_recording = ctx.link().callback(|_| Msg::ToggleRecording);
+        let recording_class = classes!(self.recording.is_some().then_some("active"));
+        let toggle_step_input = ctx.link().callback(|_| Msg::ToggleStepInput);
+        let step_input_class = classes!(self.step_input.is_some().then_some("active"));
+        let rest = ctx.link().callback(|_| Msg::StepInputRest);
+        let toggle_musical_typing = ctx.link().callback(|_| Msg::ToggleMusicalTyping);
+        let musical_typing_class = classes!(self.musical_typing.is_some().then_some("active"));
+        let toggle_metronome = ctx.link().callback(|_| Msg::ToggleMetronome);
+        let metronome_class = classes!(self.metronome.enabled.then_some("active"));
         let undo = ctx.link().callback(|_| Msg::Undo);
         let redo = ctx.link().callback(|_| Msg::Redo);
 
         html! {
             <div class="h-box frame">
                 <button onclick={ toggle }>{ "Play/Stop" }</button>
+                <button class={ recording_class } onclick={ toggle_recording }>
+                    { "Record" }
+                </button>
+                <button class={ step_input_class } onclick={ toggle_step_input }>
+                    { "Step input" }
+                </button>
+                if self.step_input.is_some() {
+                    <button title="Space" onclick={ rest }>{ "Rest" }</button>
+                }
+                <button class={ musical_typing_class } onclick={ toggle_musical_typing }
+                        title="Play notes with the keys A to ; (octave Z/X, velocity C/V)">
+                    { "Musical typing" }
+                </button>
+                if let Some(typing) = &self.musical_typing {
+                    <span>{ typing.status() }</span>
+                }
+                <button class={ loop_class } onclick={ toggle_loop }>{ "Loop" }</button>
+                <button class={ metronome_class } onclick={ toggle_metronome }>
+                    { "Metronome" }
+                </button>
                 <button onclick={ undo }>{ "Undo" }</button>
                 <button onclick={ redo }>{ "Redo" }</button>
             </div>
@@ -61,32 +163,43 @@ impl Model {
                 .target_dyn_into::<HtmlInputElement>()
                 .and_then(|input| {
                     let parse = input.value().parse().ok();
-                    parse.and_then(|bpm| Some(Msg::SetBpm(bpm)))
+
+                    // Browsers don't enforce `min` and `max` while typing.
+                    parse
+                        .filter(|bpm: &f64| (MIN_BPM..=MAX_BPM).contains(bpm))
+                        .map(Msg::SetBpm)
                 })
         });
+        let onblur = ctx.link().callback(|_: FocusEvent| Msg::EndContinuousEdit);
 
         html! {
             <div class="v-box frame">
                 <span>{ "BPM" }</span>
-                <input type="number" value={ self.project.bpm.to_string() }
-                       min="1" max="5000" size="5" { oninput }/>
+                <input type="number" value={ self.project.tempo_map.initial_bpm().to_string() }
+                       min={ MIN_BPM.to_string() } max={ MAX_BPM.to_string() } size="5"
+                       { oninput } { onblur }/>
             </div>
         }
     }
 
     pub fn view_time_signature(&self, ctx: &Context<Self>) -> Html {
-        let top_values = time_signature_options(&[2u32, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+        let top_values = time_signature_options(
+            &[2u32, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12],
+            self.project.time_signature_map.initial().top,
+        );
 
         let on_top_change = ctx.link().batch_callback(|event: Event| {
-            select_get_value(event)
-                .and_then(|top| Some(Msg::SetTimeSignatureTop(top.parse().unwrap())))
+            select_get_value(event).map(|top| Msg::SetTimeSignatureTop(top.parse().unwrap()))
         });
 
-        let bottom_values = time_signature_options(&[4u32, 8, 16]);
+        let bottom_values = time_signature_options(
+            &[2u32, 4, 8, 16],
+            self.project.time_signature_map.initial().bottom,
+        );
 
         let on_bottom_change = ctx.link().batch_callback(|event: Event| {
             select_get_value(event)
-                .and_then(|bottom| Some(Msg::SetTimeSignatureBottom(bottom.parse().unwrap())))
+                .map(|bottom| Msg::SetTimeSignatureBottom(bottom.parse().unwrap()))
         });
 
         html! {
@@ -105,6 +218,69 @@ impl Model {
         }
     }
 
+    pub fn view_grid(&self, ctx: &Context<Self>) -> Html {
+        html! {
+            <div class="v-box frame">
+                <span>{ "Grid" }</span>
+                { self.view_grid_select(ctx, Grid::all(), self.project.grid, Msg::SetGrid) }
+            </div>
+        }
+    }
+
+    pub fn view_grid_select(
+        &self,
+        ctx: &Context<Self>,
+        grids: Vec<Grid>,
+        selected: Grid,
+        message: fn(Grid) -> Msg,
+    ) -> Html {
+        let options: Vec<Html> = grids
+            .iter()
+            .enumerate()
+            .map(|(index, grid)| {
+                html! {
+                    <option value={ index.to_string() } selected={ *grid == selected }>
+                        { grid.label() }
+                    </option>
+                }
+            })
+            .collect();
+
+        let onchange = ctx.link().batch_callback(move |event: Event| {
+            select_get_value(event)
+                .and_then(|index| index.parse::<usize>().ok())
+                .and_then(|index| grids.get(index).copied())
+                .map(message)
+        });
+
+        html! {
+            <select { onchange }>
+                { for options }
+            </select>
+        }
+    }
+
+    pub fn view_zoom(&self, ctx: &Context<Self>) -> Html {
+        let zoom_button = |label: &'static str, message: fn() -> Msg| {
+            let onclick = ctx.link().callback(move |_| message());
+            html! { <button { onclick }>{ label }</button> }
+        };
+
+        html! {
+            <div class="v-box frame">
+                <span>{ "Zoom" }</span>
+                <div class="h-box">
+                    <span>{ "Time" }</span>
+                    { zoom_button("-", || Msg::ZoomHorizontal(1.0 / ZOOM_STEP)) }
+                    { zoom_button("+", || Msg::ZoomHorizontal(ZOOM_STEP)) }
+                    <span>{ "Pitch" }</span>
+                    { zoom_button("-", || Msg::ZoomVertical(1.0 / ZOOM_STEP)) }
+                    { zoom_button("+", || Msg::ZoomVertical(ZOOM_STEP)) }
+                </div>
+            </div>
+        }
+    }
+
     pub fn view_output_selection(&self, ctx: &Context<Self>) -> Html {
         let output_devices = self.get_output_devices();
 
@@ -143,7 +319,7 @@ impl Model {
                 let name = output.name();
 
                 if let Some(name) = name {
-                    name.to_string() == output_device_name
+                    name == output_device_name
                 } else {
                     false
                 }
@@ -167,12 +343,59 @@ impl Model {
         }
     }
 
+    pub fn view_input_selection(&self, ctx: &Context<Self>) -> Html {
+        let input_devices = self.get_input_devices();
+
+        let selected_input_name = self
+            .selected_input
+            .as_ref()
+            .and_then(|selected_input| selected_input.name());
+
+        let device_options = input_devices.iter().map(|input| {
+            let selected = selected_input_name.is_some() && input.name() == selected_input_name;
+
+            html! {
+                <option value={ input.name() } { selected }>
+                    { input.name().unwrap_or("No Name".to_string()) }
+                </option>
+            }
+        });
+
+        let input_devices_copy = input_devices.clone();
+
+        let onchange = ctx.link().callback(move |event: Event| {
+            let input_device_name = select_get_value(event);
+
+            let input_device = input_devices_copy
+                .iter()
+                .find(|input| input.name().is_some() && input.name() == input_device_name)
+                .cloned();
+
+            Msg::SetInputDevice(input_device)
+        });
+
+        html! {
+            <div class="v-box frame">
+                <span>{ "MIDI Input" }</span>
+                <select { onchange }>
+                    <option value="" selected={ self.selected_input.is_none() }>{ "None" }</option>
+                    { for device_options }
+                </select>
+            </div>
+        }
+    }
+
     pub fn view_project_panel(&self, ctx: &Context<Self>) -> Html {
         html! {
             <div id="project-panel" class="v-box frame dark">
                 { self.view_project_info(ctx) }
+                { self.view_time_signature_changes(ctx) }
                 { self.view_track_select(ctx) }
                 { self.view_track_info(ctx) }
+                { self.view_automation(ctx) }
+                { self.view_quantize(ctx) }
+                { self.view_metronome(ctx) }
+                { self.view_history(ctx) }
             </div>
         }
     }
@@ -181,15 +404,113 @@ impl Model {
         let change_project_name = ctx.link().batch_callback(|event: InputEvent| {
             event
                 .target_dyn_into::<HtmlInputElement>()
-                .and_then(|input| Some(Msg::SetProjectName(input.value())))
+                .map(|input| Msg::SetProjectName(input.value()))
         });
+        let end_edit = ctx.link().callback(|_: FocusEvent| Msg::EndContinuousEdit);
+
+        let open = ctx.link().callback(|_| Msg::OpenProject);
+        let save = ctx.link().callback(|_| Msg::SaveProject);
 
         html! {
             <div class="v-box-left frame full-width">
                 <div class="full-width">
                     <span>{ "Project: " }</span>
-                    <input value={ self.project.name.to_string() } oninput={ change_project_name }/>
+                    <input value={ self.project.name.to_string() } oninput={ change_project_name }
+                           onblur={ end_edit }/>
+                </div>
+                <div class="h-box full-width">
+                    <button onclick={ open }>{ "Open project" }</button>
+                    <button onclick={ save }>{ "Save project" }</button>
+                </div>
+            </div>
+        }
+    }
+
+    pub fn view_time_signature_changes(&self, ctx: &Context<Self>) -> Html {
+        let changes = self
+            .project
+            .time_signature_map
+            .changes
+            .iter()
+            .enumerate()
+            .skip(1);
+
+        let rows = changes.map(|(index, change)| {
+            let change_copy = change.clone();
+
+            let on_measure_input = ctx.link().batch_callback(move |event: InputEvent| {
+                event
+                    .target_dyn_into::<HtmlInputElement>()
+                    .and_then(|input| input.value().parse::<u32>().ok())
+                    .filter(|measure| *measure >= 2)
+                    .map(|measure| {
+                        let mut change = change_copy.clone();
+                        // Measures are displayed one-based.
+                        change.measure = measure - 1;
+                        Msg::EditTimeSignatureChange(index, change)
+                    })
+            });
+
+            let change_copy = change.clone();
+
+            let on_top_change = ctx.link().batch_callback(move |event: Event| {
+                select_get_value(event)
+                    .and_then(|top| top.parse().ok())
+                    .map(|top| {
+                        let mut change = change_copy.clone();
+                        change.time_signature.top = top;
+                        Msg::EditTimeSignatureChange(index, change)
+                    })
+            });
+
+            let change_copy = change.clone();
+
+            let on_bottom_change = ctx.link().batch_callback(move |event: Event| {
+                select_get_value(event)
+                    .and_then(|bottom| bottom.parse().ok())
+                    .map(|bottom| {
+                        let mut change = change_copy.clone();
+                        change.time_signature.bottom = bottom;
+                        Msg::EditTimeSignatureChange(index, change)
+                    })
+            });
+
+            let delete = ctx
+                .link()
+                .callback(move |_| Msg::DeleteTimeSignatureChange(index));
+
+            let top_values = time_signature_options(
+                &[1u32, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 15],
+                change.time_signature.top,
+            );
+
+            let bottom_values =
+                time_signature_options(&[2u32, 4, 8, 16], change.time_signature.bottom);
+
+            html! {
+                <div class="h-box full-width">
+                    <span>{ "Measure" }</span>
+                    <input type="number" value={ (change.measure + 1).to_string() }
+                           min="2" size="3" oninput={ on_measure_input }/>
+                    <select onchange={ on_top_change }>
+                        { for top_values }
+                    </select>
+                    <span>{ "/" }</span>
+                    <select onchange={ on_bottom_change }>
+                        { for bottom_values }
+                    </select>
+                    <button onclick={ delete }>{ "X" }</button>
                 </div>
+            }
+        });
+
+        let add = ctx.link().callback(|_| Msg::AddTimeSignatureChange);
+
+        html! {
+            <div class="v-box-left frame full-width">
+                <span>{ "Time signature changes" }</span>
+                { for rows }
+                <button onclick={ add }>{ "Add at playhead" }</button>
             </div>
         }
     }
@@ -211,7 +532,7 @@ impl Model {
                 value
                     .parse()
                     .ok()
-                    .and_then(|index| Some(Msg::SelectTrack(index)))
+                    .map(Msg::SelectTrack)
                     .or_else(|| Some(Msg::DeselectTrack))
             })
         });
@@ -232,6 +553,7 @@ impl Model {
         };
 
         let export = ctx.link().callback(|_| Msg::ExportMidi);
+        let import = ctx.link().callback(|_| Msg::ImportMidi);
 
         html! {
             <div class="v-box-left frame full-width">
@@ -240,7 +562,10 @@ impl Model {
                     <button onclick={ create }>{ "Create" }</button>
                     <button onclick={ delete }>{ "Delete" }</button>
                 </div>
-                <button onclick={ export }>{ "Export MIDI" }</button>
+                <div class="h-box full-width">
+                    <button onclick={ import }>{ "Import MIDI" }</button>
+                    <button onclick={ export }>{ "Export MIDI" }</button>
+                </div>
             </div>
         }
     }
@@ -251,7 +576,7 @@ impl Model {
                 let on_track_name_input = ctx.link().batch_callback(|event: InputEvent| {
                     event
                         .target_dyn_into::<HtmlInputElement>()
-                        .and_then(|input| Some(Msg::RenameSelectedTrack(input.value())))
+                        .map(|input| Msg::RenameSelectedTrack(input.value()))
                 });
 
                 let on_track_instrument_input = ctx.link().batch_callback(|event: InputEvent| {
@@ -262,7 +587,7 @@ impl Model {
                                 // Subtract one because we input the instrument number, not index.
                                 instrument -= 1;
 
-                                if instrument >= 0 && instrument <= 127 {
+                                if (0..=127).contains(&instrument) {
                                     Some(Msg::SetSelectedTrackInstrument(instrument as _))
                                 } else {
                                     None
@@ -271,17 +596,49 @@ impl Model {
                         })
                 });
 
+                let channel_options = channel_options(track.channel);
+
+                let on_track_channel_change = ctx.link().batch_callback(|event: Event| {
+                    select_get_value(event)
+                        .and_then(|channel| channel.parse().ok())
+                        .map(Msg::SetSelectedTrackChannel)
+                });
+
+                let on_track_bend_range_input = ctx.link().batch_callback(|event: InputEvent| {
+                    event
+                        .target_dyn_into::<HtmlInputElement>()
+                        .and_then(|input| input.value().parse::<u8>().ok())
+                        .filter(|bend_range| *bend_range <= MAX_BEND_RANGE)
+                        .map(Msg::SetSelectedTrackBendRange)
+                });
+
+                let end_edit = ctx.link().callback(|_: FocusEvent| Msg::EndContinuousEdit);
+
                 html! {
                     <>
                         <div class="h-box full-width">
                             <span>{ "Name: "}</span>
-                            <input value={ track.name.to_string() } oninput={ on_track_name_input }/>
+                            <input value={ track.name.to_string() } oninput={ on_track_name_input }
+                                   onblur={ end_edit.clone() }/>
                         </div>
                         <div class="h-box full-width">
                             <span>{ "Instrument: "}</span>
                             <input type="number" value={ (track.instrument + 1).to_string() }
                                    min="1" max="128" oninput={ on_track_instrument_input }
-                                   size="3"/>
+                                   onblur={ end_edit.clone() } size="3"/>
+                        </div>
+                        <div class="h-box full-width">
+                            <span>{ "Channel: "}</span>
+                            <select onchange={ on_track_channel_change }>
+                                { for channel_options }
+                            </select>
+                        </div>
+                        <div class="h-box full-width">
+                            <span>{ "Bend range: "}</span>
+                            <input type="number" value={ track.bend_range.to_string() }
+                                   min="0" max={ MAX_BEND_RANGE.to_string() }
+                                   oninput={ on_track_bend_range_input }
+                                   onblur={ end_edit } size="3"/>
                         </div>
                     </>
                 }
@@ -299,125 +656,639 @@ impl Model {
         }
     }
 
+    pub fn view_automation(&self, ctx: &Context<Self>) -> Html {
+        let track = match self.selected_track_index {
+            Some(index) => &self.project.tracks[index],
+            None => return html! {},
+        };
+
+        let lanes = track.control_lanes.iter().map(|lane| {
+            let controller = lane.controller;
+            let delete = ctx
+                .link()
+                .callback(move |_| Msg::DeleteControlLane(controller));
+
+            html! {
+                <div class="h-box full-width">
+                    <span>{ controller_name(controller) }</span>
+                    <button onclick={ delete }>{ "Delete" }</button>
+                </div>
+            }
+        });
+
+        let available: Vec<u8> = (0..=127u8)
+            .filter(|&controller| {
+                is_automatable(controller) && track.control_lane_index(controller).is_none()
+            })
+            .collect();
+
+        // The picked controller may have a lane by now.
+        let picked = available
+            .iter()
+            .copied()
+            .find(|&controller| controller == self.new_control_lane_controller)
+            .or_else(|| available.first().copied());
+
+        let controller_options = available.iter().map(|&controller| {
+            html! {
+                <option value={ controller.to_string() } selected={ Some(controller) == picked }>
+                    { controller_name(controller) }
+                </option>
+            }
+        });
+
+        let on_controller_change = ctx.link().batch_callback(|event: Event| {
+            select_get_value(event)
+                .and_then(|controller| controller.parse().ok())
+                .map(Msg::SetNewControlLaneController)
+        });
+
+        let add = ctx
+            .link()
+            .batch_callback(move |_| picked.map(Msg::AddControlLane));
+
+        html! {
+            <div class="v-box-left frame full-width">
+                <span>{ "Automation" }</span>
+                { for lanes }
+                <div class="h-box full-width">
+                    <select onchange={ on_controller_change }>
+                        { for controller_options }
+                    </select>
+                    <button onclick={ add }>{ "Add lane" }</button>
+                </div>
+            </div>
+        }
+    }
+
+    pub fn view_quantize(&self, ctx: &Context<Self>) -> Html {
+        let settings = &self.quantize_settings;
+
+        let grids = Grid::all()
+            .into_iter()
+            .filter(|grid| grid.interval().is_some())
+            .collect();
+
+        let percentage_input = |message: fn(f64) -> Msg| {
+            ctx.link().batch_callback(move |event: InputEvent| {
+                event
+                    .target_dyn_into::<HtmlInputElement>()
+                    .and_then(|input| input.value().parse::<f64>().ok())
+                    .filter(|percentage| (0.0..=100.0).contains(percentage))
+                    .map(|percentage| message(percentage / 100.0))
+            })
+        };
+
+        let on_quantize_ends_change = ctx.link().batch_callback(|event: Event| {
+            event
+                .target_dyn_into::<HtmlInputElement>()
+                .map(|input| Msg::SetQuantizeEnds(input.checked()))
+        });
+
+        let quantize = ctx.link().callback(|_| Msg::Quantize);
+
+        html! {
+            <div class="v-box-left frame full-width">
+                <div class="h-box full-width">
+                    <span>{ "Grid: " }</span>
+                    { self.view_grid_select(ctx, grids, settings.grid, Msg::SetQuantizeGrid) }
+                </div>
+                <div class="h-box full-width">
+                    <span>{ "Strength (%): " }</span>
+                    <input type="number" value={ (settings.strength * 100.0).to_string() }
+                           min="0" max="100" size="3"
+                           oninput={ percentage_input(Msg::SetQuantizeStrength) }/>
+                </div>
+                <div class="h-box full-width">
+                    <span>{ "Swing (%): " }</span>
+                    <input type="number" value={ (settings.swing * 100.0).to_string() }
+                           min="0" max="100" size="3"
+                           oninput={ percentage_input(Msg::SetQuantizeSwing) }/>
+                </div>
+                <div class="h-box full-width">
+                    <input type="checkbox" checked={ settings.quantize_ends }
+                           onchange={ on_quantize_ends_change }/>
+                    <span>{ "Quantize note ends" }</span>
+                </div>
+                <button onclick={ quantize }>{ "Quantize" }</button>
+            </div>
+        }
+    }
+
+    pub fn view_metronome(&self, ctx: &Context<Self>) -> Html {
+        let settings = &self.metronome;
+
+        let channel_options = channel_options(settings.channel);
+
+        let on_channel_change = ctx.link().batch_callback(|event: Event| {
+            select_get_value(event)
+                .and_then(|channel| channel.parse().ok())
+                .map(Msg::SetMetronomeChannel)
+        });
+
+        let pitch_input = |message: fn(u8) -> Msg| {
+            ctx.link().batch_callback(move |event: InputEvent| {
+                event
+                    .target_dyn_into::<HtmlInputElement>()
+                    .and_then(|input| input.value().parse::<u8>().ok())
+                    .filter(|pitch| *pitch <= 127)
+                    .map(message)
+            })
+        };
+
+        let count_in_options = (0..=MAX_COUNT_IN).map(|measures| {
+            let label = match measures {
+                0 => "Off".to_string(),
+                1 => "1 measure".to_string(),
+                _ => format!("{} measures", measures),
+            };
+
+            html! {
+                <option value={ measures.to_string() } selected={ measures == settings.count_in }>
+                    { label }
+                </option>
+            }
+        });
+
+        let on_count_in_change = ctx.link().batch_callback(|event: Event| {
+            select_get_value(event)
+                .and_then(|measures| measures.parse().ok())
+                .map(Msg::SetCountIn)
+        });
+
+        html! {
+            <div class="v-box-left frame full-width">
+                <div class="h-box full-width">
+                    <span>{ "Click channel: " }</span>
+                    <select onchange={ on_channel_change }>
+                        { for channel_options }
+                    </select>
+                </div>
+                <div class="h-box full-width">
+                    <span>{ format!("Accent ({}): ", note_name(settings.accent_pitch)) }</span>
+                    <input type="number" value={ settings.accent_pitch.to_string() }
+                           min="0" max="127" size="3"
+                           oninput={ pitch_input(Msg::SetMetronomeAccentPitch) }/>
+                </div>
+                <div class="h-box full-width">
+                    <span>{ format!("Beat ({}): ", note_name(settings.pitch)) }</span>
+                    <input type="number" value={ settings.pitch.to_string() }
+                           min="0" max="127" size="3"
+                           oninput={ pitch_input(Msg::SetMetronomePitch) }/>
+                </div>
+                <div class="h-box full-width">
+                    <span>{ "Count-in: " }</span>
+                    <select onchange={ on_count_in_change }>
+                        { for count_in_options }
+                    </select>
+                </div>
+            </div>
+        }
+    }
+
+    pub fn view_history(&self, ctx: &Context<Self>) -> Html {
+        let nodes = self.history.nodes();
+
+        // Branches are indented one level deeper than the branch they split off from.
+        let mut levels = vec![0; nodes.len()];
+        let mut has_child = vec![false; nodes.len()];
+
+        for (index, node) in nodes.iter().enumerate() {
+            if let Some(parent) = node.parent {
+                levels[index] = levels[parent] + has_child[parent] as usize;
+                has_child[parent] = true;
+            }
+        }
+
+        let applied = self.history.applied();
+
+        let entries = nodes.iter().enumerate().rev().map(|(index, node)| {
+            let mut class = classes!("history-entry");
+
+            if index == self.history.current() {
+                class.push("current");
+            } else if !applied[index] {
+                class.push("undone");
+            }
+
+            let onclick = ctx.link().callback(move |_| Msg::JumpToHistory(index));
+            let style = format!("padding-left: {}em;", levels[index]);
+
+            html! {
+                <button { class } { style } { onclick }>{ &node.description }</button>
+            }
+        });
+
+        html! {
+            <div class="v-box-left frame full-width">
+                <span>{ "History" }</span>
+                <div id="history-list" class="v-box-left full-width">
+                    { for entries }
+                </div>
+            </div>
+        }
+    }
+
     pub fn view_piano_roll(&self, ctx: &Context<Self>) -> Html {
         let progress_bar_on_mouse_down = ctx
             .link()
             .callback(|event: MouseEvent| Msg::ProgressBarMouseDown(event));
 
-        let progress_bar_on_mouse_up = ctx.link().callback(|_: MouseEvent| Msg::ProgressBarMouseUp);
-
         let piano_roll_on_mouse_down = ctx
             .link()
             .callback(|event: MouseEvent| Msg::PianoRollMouseDown(event));
 
-        let piano_roll_on_mouse_up = ctx.link().callback(|_: MouseEvent| Msg::PianoRollMouseUp);
-
         let on_mouse_move = ctx
             .link()
             .callback(|event: MouseEvent| Msg::MouseMove(event));
 
         let oncontextmenu = |event: MouseEvent| event.prevent_default();
 
-        let width = 10000.0;
-
-        let measure_width = self.project.time_signature.measure_width();
-        let interval_width = WHOLE_NOTE_WIDTH * MIN_INTERVAL;
+        let width = 10000.0 * self.zoom.horizontal;
 
-        let piano_view_style = format!(
-            "width: {}px; grid-template-columns: {}px auto;",
-            width, PIANO_KEYS_WIDTH
+        // Ctrl+wheel would zoom the whole page, so the listener can't be passive.
+        let onwheel = ctx
+            .link()
+            .callback_with_passive(false, |event: WheelEvent| Msg::PianoRollWheel(event));
+
+        let mut lanes = vec![
+            self.view_velocity_lane(ctx),
+            self.view_tempo_lane(ctx, width),
+        ];
+
+        lanes.extend(self.act_on_selected_track(|track| self.view_bend_lane(ctx, track, width)));
+
+        lanes.extend(
+            self.act_on_selected_track(|track| {
+                track
+                    .control_lanes
+                    .iter()
+                    .map(|lane| self.view_control_lane(ctx, lane, width))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default(),
         );
 
-        let grid_lines_style = format!(
-            r#"
-                background-size: {}px {}px;
-                background-image:
-                    linear-gradient(black 2px, transparent 1px),
-                    linear-gradient(90deg, black 2px, transparent 1px);
-            "#,
-            interval_width, NOTE_RECT_HEIGHT
-        );
+        let lane_rows: Vec<String> = lanes
+            .iter()
+            .map(|lane| format!("{}px", lane.height))
+            .collect();
 
-        let measure_lines_style = format!(
-            r#"
-                background-size: {}px 100%;
-                background-image: linear-gradient(90deg, black 4px, transparent 1px);
-            "#,
-            measure_width
+        let piano_view_style = format!(
+            "width: {}px; grid-template-columns: {}px auto; grid-template-rows: 1.1em auto {};",
+            width,
+            PIANO_KEYS_WIDTH,
+            lane_rows.join(" ")
         );
 
+        let grid_lines_style = match self.project.grid.interval() {
+            Some(interval) => format!(
+                r#"
+                    background-size: {}px {}px;
+                    background-image:
+                        linear-gradient(black 2px, transparent 1px),
+                        linear-gradient(90deg, black 2px, transparent 1px);
+                "#,
+                interval * self.zoom.whole_note_width(),
+                self.zoom.note_height()
+            ),
+            // Without a grid, only the pitch rows are drawn.
+            None => format!(
+                r#"
+                    background-size: 100% {}px;
+                    background-image: linear-gradient(black 2px, transparent 1px);
+                "#,
+                self.zoom.note_height()
+            ),
+        };
+
         html! {
-            <div id="piano-wrapper">
+            <div id="piano-wrapper" { onwheel }>
                 <div id="piano-view" style={ piano_view_style }>
                     <div id="piano-keys" class="v-box-left no-gap">
                         { for self.view_piano_keys(ctx) }
                     </div>
                     <svg id="progress-bar" width="100%" height="100%">
+                        { self.view_loop_region() }
                         { for self.view_measure_numbers(width) }
                     </svg>
                     <div id="progress-bar-clickable-area"
                          onmousedown={ progress_bar_on_mouse_down }
-                         onmouseup={ progress_bar_on_mouse_up }
                          onmousemove= { on_mouse_move.clone() }/>
                     <div class="overlay" style={ grid_lines_style }/>
-                    <div class="overlay" style={ measure_lines_style }/>
                     <svg id="piano-roll" width="100%" height="100%">
+                        { for self.view_measure_lines(width) }
                         { for self.view_notes() }
+                        { self.view_selection_rect() }
                         <line ref={ self.progress_line.clone() } y1="0" y2="100%"
                               stroke="white" stroke-width="2"/>
                     </svg>
                     <div ref={ self.piano_roll_area.clone() } id="piano-roll-clickable-area"
                          onmousedown={ piano_roll_on_mouse_down }
-                         onmouseup={ piano_roll_on_mouse_up }
-                         onmousemove={ on_mouse_move }
+                         onmousemove={ on_mouse_move.clone() }
                          { oncontextmenu }/>
+                    { self.view_lanes(lanes, on_mouse_move) }
                 </div>
             </div>
         }
     }
 
-    pub fn view_measure_numbers(&self, width: f64) -> Vec<Html> {
-        let mut measure_numbers = Vec::new();
+    pub fn view_lanes(&self, lanes: Vec<Lane>, on_mouse_move: Callback<MouseEvent>) -> Html {
+        let mut bottom: f64 = lanes.iter().map(|lane| lane.height).sum();
 
-        let mut progress = 0.0;
-        let mut x = 0.0;
+        lanes
+            .into_iter()
+            .enumerate()
+            .map(|(index, lane)| {
+                bottom -= lane.height;
 
-        let mut measure_number = 1;
+                // The first two rows hold the progress bar and the piano roll.
+                let style = format!("grid-row: {}; bottom: {}px;", index + 3, bottom);
 
-        let measure_length = self.project.time_signature.measure_length();
+                let Lane {
+                    label,
+                    content,
+                    onmousedown,
+                    ..
+                } = lane;
 
-        while x <= width {
-            if progress % measure_length <= 1e-5 {
-                let x = x + PIANO_KEYS_WIDTH;
+                html! {
+                    <>
+                        <div class="lane-label" style={ style.clone() }>{ label }</div>
+                        <svg class="lane" style={ style.clone() } width="100%" height="100%">
+                            { content }
+                        </svg>
+                        <div class="lane-clickable-area" { style } { onmousedown }
+                             onmousemove={ on_mouse_move.clone() }
+                             oncontextmenu={ |event: MouseEvent| event.prevent_default() }/>
+                    </>
+                }
+            })
+            .collect()
+    }
 
-                measure_numbers.push(html! {
-                    <text class="measure-number" x={ x.to_string() } y="50%">
-                        { measure_number.to_string() }
-                    </text>
-                });
+    pub fn view_velocity_lane(&self, ctx: &Context<Self>) -> Lane {
+        let bars: Vec<Html> = self
+            .act_on_selected_track(|track| {
+                track
+                    .notes
+                    .iter()
+                    .map(|note| {
+                        let height = note.velocity as f64 / 127.0 * VELOCITY_LANE_HEIGHT;
+
+                        let x = (note.screen_x(self.zoom) - VELOCITY_BAR_WIDTH / 2.0).to_string();
+                        let y = (VELOCITY_LANE_HEIGHT - height).to_string();
+                        let width = VELOCITY_BAR_WIDTH.to_string();
+                        let height = height.to_string();
+
+                        html! {
+                            <rect { x } { y } { width } { height } fill="green"
+                                  stroke="black" stroke-width="1"/>
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Lane {
+            label: "Vel.".to_string(),
+            height: VELOCITY_LANE_HEIGHT,
+            content: html! { for bars },
+            onmousedown: ctx
+                .link()
+                .callback(|event: MouseEvent| Msg::VelocityLaneMouseDown(event)),
+        }
+    }
+
+    pub fn view_tempo_lane(&self, ctx: &Context<Self>, width: f64) -> Lane {
+        let tempo_map = &self.project.tempo_map;
+        let events = &tempo_map.events;
+
+        let mut points = Vec::new();
+
+        for (index, event) in events.iter().enumerate() {
+            let y = event.screen_y(TEMPO_LANE_HEIGHT);
+            points.push(format!("{},{}", event.screen_x(self.zoom), y));
+
+            let end_x = events
+                .get(index + 1)
+                .map(|next| next.screen_x(self.zoom))
+                .unwrap_or(width);
+
+            // Ramps are drawn as slopes towards the next event, jumps as steps.
+            let end_y = match events.get(index + 1) {
+                Some(next) if event.ramp => next.screen_y(TEMPO_LANE_HEIGHT),
+                _ => y,
+            };
+
+            points.push(format!("{},{}", end_x, end_y));
+        }
 
-                measure_number += 1;
+        let handles = events.iter().map(|event| {
+            let cx = event.screen_x(self.zoom).to_string();
+            let cy = event.screen_y(TEMPO_LANE_HEIGHT).to_string();
+
+            let fill = if event.ramp { "orange" } else { "green" };
+
+            html! {
+                <circle { cx } { cy } r={ TEMPO_POINT_RADIUS.to_string() } { fill }
+                        stroke="black" stroke-width="1">
+                    <title>{ format!("{} BPM", event.bpm) }</title>
+                </circle>
             }
+        });
 
-            progress += MIN_INTERVAL;
-            x = progress * WHOLE_NOTE_WIDTH;
+        Lane {
+            label: "BPM".to_string(),
+            height: TEMPO_LANE_HEIGHT,
+            content: html! {
+                <>
+                    <polyline points={ points.join(" ") } fill="none" stroke="green"
+                              stroke-width="2"/>
+                    { for handles }
+                </>
+            },
+            onmousedown: ctx
+                .link()
+                .callback(|event: MouseEvent| Msg::TempoLaneMouseDown(event)),
         }
+    }
+
+    pub fn view_control_lane(&self, ctx: &Context<Self>, lane: &ControlLane, width: f64) -> Lane {
+        let mut points = Vec::new();
 
-        measure_numbers
+        // Values hold until the next point, so the curve is drawn as steps.
+        for (point_index, point) in lane.points.iter().enumerate() {
+            let y = point.screen_y(CONTROL_LANE_HEIGHT);
+            let end_x = lane
+                .points
+                .get(point_index + 1)
+                .map(|next| next.screen_x(self.zoom))
+                .unwrap_or(width);
+
+            points.push(format!("{},{}", point.screen_x(self.zoom), y));
+            points.push(format!("{},{}", end_x, y));
+        }
+
+        let handles = lane.points.iter().map(|point| {
+            let cx = point.screen_x(self.zoom).to_string();
+            let cy = point.screen_y(CONTROL_LANE_HEIGHT).to_string();
+
+            html! {
+                <circle { cx } { cy } r={ CONTROL_POINT_RADIUS.to_string() } fill="green"
+                        stroke="black" stroke-width="1">
+                    <title>{ point.value.to_string() }</title>
+                </circle>
+            }
+        });
+
+        let controller = lane.controller;
+
+        Lane {
+            label: controller_label(controller),
+            height: CONTROL_LANE_HEIGHT,
+            content: html! {
+                <>
+                    <polyline points={ points.join(" ") } fill="none" stroke="green"
+                              stroke-width="2"/>
+                    { for handles }
+                </>
+            },
+            onmousedown: ctx.link().callback(move |event: MouseEvent| {
+                Msg::AutomationLaneMouseDown(AutomationLane::Control(controller), event)
+            }),
+        }
+    }
+
+    pub fn view_bend_lane(&self, ctx: &Context<Self>, track: &Track, width: f64) -> Lane {
+        let mut points: Vec<String> = track
+            .pitch_bend
+            .iter()
+            .map(|point| {
+                format!(
+                    "{},{}",
+                    point.screen_x(self.zoom),
+                    point.screen_y(BEND_LANE_HEIGHT)
+                )
+            })
+            .collect();
+
+        // The last bend holds until the end.
+        if let Some(last) = track.pitch_bend.last() {
+            points.push(format!("{},{}", width, last.screen_y(BEND_LANE_HEIGHT)));
+        }
+
+        let handles = track.pitch_bend.iter().map(|point| {
+            let cx = point.screen_x(self.zoom).to_string();
+            let cy = point.screen_y(BEND_LANE_HEIGHT).to_string();
+            let semitones = point.value as f64 / -(MIN_BEND as f64) * track.bend_range as f64;
+
+            html! {
+                <circle { cx } { cy } r={ CONTROL_POINT_RADIUS.to_string() } fill="green"
+                        stroke="black" stroke-width="1">
+                    <title>{ format!("{:+.2} semitones", semitones) }</title>
+                </circle>
+            }
+        });
+
+        let center = (BEND_LANE_HEIGHT / 2.0).to_string();
+
+        Lane {
+            label: "Bend".to_string(),
+            height: BEND_LANE_HEIGHT,
+            content: html! {
+                <>
+                    <line x1="0" y1={ center.clone() } x2="100%" y2={ center } stroke="gray"
+                          stroke-width="1" stroke-dasharray="4"/>
+                    <polyline points={ points.join(" ") } fill="none" stroke="green"
+                              stroke-width="2"/>
+                    { for handles }
+                </>
+            },
+            onmousedown: ctx.link().callback(|event: MouseEvent| {
+                Msg::AutomationLaneMouseDown(AutomationLane::PitchBend, event)
+            }),
+        }
+    }
+
+    pub fn view_loop_region(&self) -> Html {
+        let loop_region = &self.project.loop_region;
+
+        let start = PIANO_KEYS_WIDTH + loop_region.start * self.zoom.whole_note_width();
+        let end = PIANO_KEYS_WIDTH + loop_region.end * self.zoom.whole_note_width();
+
+        let class = classes!("loop-region", loop_region.enabled.then_some("active"));
+
+        html! {
+            <g { class }>
+                <rect x={ start.to_string() } y="0" width={ (end - start).to_string() }
+                      height="100%"/>
+                <line x1={ start.to_string() } x2={ start.to_string() } y1="0" y2="100%"/>
+                <line x1={ end.to_string() } x2={ end.to_string() } y1="0" y2="100%"/>
+            </g>
+        }
+    }
+
+    pub fn view_measure_numbers(&self, width: f64) -> Vec<Html> {
+        let map = &self.project.time_signature_map;
+
+        map.measures(width / self.zoom.whole_note_width())
+            .into_iter()
+            .map(|measure| {
+                let x = measure.offset * self.zoom.whole_note_width() + PIANO_KEYS_WIDTH;
+
+                let mut label = (measure.index + 1).to_string();
+
+                // Mark the measures where the time signature changes.
+                if measure.index > 0
+                    && map
+                        .changes
+                        .iter()
+                        .any(|change| change.measure == measure.index)
+                {
+                    let time_signature = measure.time_signature;
+                    label = format!(
+                        "{} ({}/{})",
+                        label, time_signature.top, time_signature.bottom
+                    );
+                }
+
+                html! {
+                    <text class="measure-number" x={ x.to_string() } y="50%">
+                        { label }
+                    </text>
+                }
+            })
+            .collect()
+    }
+
+    pub fn view_measure_lines(&self, width: f64) -> Vec<Html> {
+        self.project
+            .time_signature_map
+            .measures(width / self.zoom.whole_note_width())
+            .into_iter()
+            .map(|measure| {
+                // Offset by half the stroke width so the line starts at the measure boundary.
+                let x = (measure.offset * self.zoom.whole_note_width() + 2.0).to_string();
+
+                html! {
+                    <line x1={ x.clone() } x2={ x } y1="0" y2="100%" stroke="black" stroke-width="4"/>
+                }
+            })
+            .collect()
     }
 
     pub fn view_piano_keys(&self, ctx: &Context<Self>) -> Vec<Html> {
         (0..=127)
             .rev()
             .map(|pitch| {
-                let instrument = self
-                    .selected_track_index
-                    .map(|index| self.project.tracks[index].instrument)
-                    .unwrap_or(0);
+                let (channel, instrument) = self
+                    .act_on_selected_track(|track| (track.channel, track.instrument))
+                    .unwrap_or((0, 0));
 
                 let onclick = ctx
                     .link()
-                    .callback(move |_: MouseEvent| Msg::PlayMidiNote(instrument, pitch));
+                    .callback(move |_: MouseEvent| Msg::PlayMidiNote(channel, instrument, pitch));
 
                 let note_name = note_name(pitch);
 
@@ -427,7 +1298,7 @@ impl Model {
                     "white-key"
                 };
 
-                let style = format!("height: {}px;", NOTE_RECT_HEIGHT);
+                let style = format!("height: {}px;", self.zoom.note_height());
 
                 html! {
                     <button { class } { style } { onclick }>
@@ -438,24 +1309,75 @@ impl Model {
             .collect()
     }
 
+    pub fn view_selection_rect(&self) -> Html {
+        match self.mouse_operation {
+            MouseOperation::SelectRect {
+                start: (x1, y1),
+                end: (x2, y2),
+            } => {
+                let x = x1.min(x2).to_string();
+                let y = y1.min(y2).to_string();
+                let width = (x1 - x2).abs().to_string();
+                let height = (y1 - y2).abs().to_string();
+
+                html! {
+                    <rect { x } { y } { width } { height } stroke="white" stroke-width="1"
+                          stroke-dasharray="4" fill="white" fill-opacity="0.1"/>
+                }
+            }
+            _ => html! {},
+        }
+    }
+
     pub fn view_notes(&self) -> Vec<Html> {
         self.act_on_selected_track(|track| {
             track
                 .notes
                 .iter()
                 .map(|note| {
-                    let x = note.screen_x().to_string();
-                    let y = note.screen_y().to_string();
-                    let width = note.screen_width().to_string();
-                    let height = note.screen_height().to_string();
+                    let x = note.screen_x(self.zoom).to_string();
+                    let y = note.screen_y(self.zoom).to_string();
+                    let width = note.screen_width(self.zoom).to_string();
+                    let height = note.screen_height(self.zoom).to_string();
+
+                    let (stroke, fill) = if self.selected_notes.contains(&note.id) {
+                        ("white", "limegreen")
+                    } else {
+                        ("black", "green")
+                    };
 
                     html! {
                         <rect { x } { y } { width } { height } rx="3" ry="3"
-                              stroke="black" stroke-width="2" fill="green"/>
+                              { stroke } stroke-width="2" { fill }/>
                     }
                 })
+                .chain(self.view_recorded_notes())
                 .collect()
         })
-        .unwrap_or(Vec::new())
+        .unwrap_or_default()
+    }
+
+    /// Notes recorded so far, which are added to the track once recording stops.
+    pub fn view_recorded_notes(&self) -> Vec<Html> {
+        let recording = match &self.recording {
+            Some(recording) if Some(recording.track_id) == self.selected_track_id() => recording,
+            _ => return Vec::new(),
+        };
+
+        recording
+            .notes
+            .iter()
+            .map(|note| {
+                let x = (note.offset * self.zoom.whole_note_width()).to_string();
+                let y = ((127 - note.pitch) as f64 * self.zoom.note_height()).to_string();
+                let width = (note.length * self.zoom.whole_note_width()).to_string();
+                let height = self.zoom.note_height().to_string();
+
+                html! {
+                    <rect { x } { y } { width } { height } rx="3" ry="3"
+                          stroke="black" stroke-width="2" fill="indianred"/>
+                }
+            })
+            .collect()
     }
 }